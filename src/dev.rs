@@ -0,0 +1,127 @@
+use crate::sources::Sources;
+use lulu::lulu::{Lulu, LuluModSource};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the watched files are checked for changes.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Watches the files the app was compiled from so it can be reloaded in place
+/// while iterating on it.
+pub struct DevReload {
+  /// Carries `State` values over to the components that still exist after a
+  /// reload.
+  pub preserve_state: bool,
+  mtimes: Vec<(PathBuf, Option<SystemTime>)>,
+  last_check: Instant,
+}
+
+impl DevReload {
+  /// Reads `--dev` and `--dev-fresh` (reload without preserving state) from
+  /// the app args. Returns `None` when neither was passed.
+  pub fn from_args(args: &[String], sources: &Sources) -> Option<Self> {
+    let fresh = args.iter().any(|a| a == "--dev-fresh");
+    if !fresh && !args.iter().any(|a| a == "--dev") {
+      return None;
+    }
+
+    let mut dev = Self {
+      preserve_state: !fresh,
+      mtimes: Vec::new(),
+      last_check: Instant::now(),
+    };
+    dev.watch(sources);
+    Some(dev)
+  }
+
+  /// Starts watching the files in `sources` from their current state.
+  pub fn watch(&mut self, sources: &Sources) {
+    self.mtimes = sources
+      .files()
+      .iter()
+      .map(|file| (file.path.clone(), modified(&file.path)))
+      .collect();
+  }
+
+  /// Whether any watched file changed since the last call, checked at most
+  /// once per `POLL_INTERVAL`.
+  pub fn changed(&mut self) -> bool {
+    if self.last_check.elapsed() < POLL_INTERVAL {
+      return false;
+    }
+    self.last_check = Instant::now();
+
+    let mut changed = false;
+    for (path, mtime) in &mut self.mtimes {
+      let current = modified(path);
+      if current != *mtime {
+        *mtime = current;
+        changed = true;
+      }
+    }
+    changed
+  }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+  std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Recompiles every module in `sources` from disk, loads modules newly
+/// imported by them, and drops Lua's cached copies so the next `require` or
+/// `exec_mod` runs the new code.
+pub fn recompile(lulu: &mut Lulu, sources: &Sources) -> mlua::Result<()> {
+  for file in sources.files() {
+    let Some(index) = lulu.mods.iter().position(|m| m.name == file.name) else {
+      continue;
+    };
+    let code = std::fs::read_to_string(&file.path)?;
+    let path = std::fs::canonicalize(&file.path)?
+      .to_string_lossy()
+      .to_string();
+    let conf = lulu.mods[index].conf.clone();
+
+    let compiled = lulu.compiler.compile(&code, Some(path), conf);
+    lulu.compiler.last_mod = None;
+    lulu.mods[index].source = LuluModSource::Code(compiled);
+  }
+
+  let imports: Vec<_> = lulu
+    .compiler
+    .importmap
+    .iter()
+    .filter(|(name, _)| !lulu.mods.iter().any(|m| &m.name == *name))
+    .map(|(name, import)| (name.clone(), import.clone()))
+    .collect();
+
+  for (name, (path_to_import, path_from, conf)) in imports {
+    let Some(parent) = path_from.as_deref().and_then(|p| Path::new(p).parent()) else {
+      continue;
+    };
+    lulu.add_mod_from_file(name, parent.join(path_to_import), conf)?;
+  }
+
+  lulu::ops::register_ops(&lulu.lua, lulu)?;
+
+  let package: mlua::Table = lulu.lua.globals().get("package")?;
+  let loaded: mlua::Table = package.get("loaded")?;
+  let preload: mlua::Table = package.get("preload")?;
+
+  for lmod in &lulu.mods {
+    loaded.set(lmod.name.as_str(), mlua::Value::Nil)?;
+    if !preload.contains_key(lmod.name.as_str())? {
+      let name = lmod.name.clone();
+      preload.set(
+        lmod.name.as_str(),
+        lulu.lua.create_function(move |lua, ()| {
+          lua
+            .globals()
+            .get::<mlua::Function>("exec_mod")?
+            .call::<mlua::Value>(name.as_str())
+        })?,
+      )?;
+    }
+  }
+
+  Ok(())
+}
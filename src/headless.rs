@@ -0,0 +1,243 @@
+use crate::sources::Sources;
+use crate::storage;
+use crate::ui::{self, LuluUiApp};
+use crate::window;
+use eframe::egui;
+use lulu::lulu::Lulu;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Frames a `--test` run may take without `--frames`, about a minute.
+const TEST_FRAMES: u64 = 3600;
+
+pub struct HeadlessOptions {
+  /// Number of frames to run before exiting, `0` runs until `ui.exit`. For
+  /// `--test` it is a limit instead, past which the run fails.
+  pub frames: u64,
+  /// Screen size, defaults to `manifest.window.size` from `lulu.conf.lua` or
+  /// 320x240.
+  pub size: Option<egui::Vec2>,
+  pub frame_time: Duration,
+  /// Runs the cases registered with `ui.test.case` instead of the app.
+  pub test: bool,
+  /// The file given with `--test=path`, run in place of `main.lua`.
+  pub entry: Option<PathBuf>,
+}
+
+impl Default for HeadlessOptions {
+  fn default() -> Self {
+    Self {
+      frames: 60,
+      size: None,
+      frame_time: Duration::from_secs_f64(1.0 / 60.0),
+      test: false,
+      entry: None,
+    }
+  }
+}
+
+impl HeadlessOptions {
+  /// Reads `--headless`, `--test`, `--test=path`, `--frames=N` and
+  /// `--size=WxH` from the app args. Returns `None` when neither `--headless`
+  /// nor `--test` was passed.
+  pub fn from_args(args: &[String]) -> Option<Self> {
+    let entry = args
+      .iter()
+      .find_map(|a| a.strip_prefix("--test="))
+      .map(PathBuf::from);
+    let test = entry.is_some() || args.iter().any(|a| a == "--test");
+    if !test && !args.iter().any(|a| a == "--headless") {
+      return None;
+    }
+
+    let mut options = Self {
+      test,
+      entry,
+      ..Self::default()
+    };
+    if test {
+      options.frames = TEST_FRAMES;
+    }
+
+    for arg in args {
+      if let Some(frames) = arg.strip_prefix("--frames=") {
+        options.frames = frames.parse::<u64>().unwrap_or(options.frames);
+      } else if let Some((w, h)) = arg.strip_prefix("--size=").and_then(|s| s.split_once('x'))
+        && let (Ok(w), Ok(h)) = (w.parse::<f32>(), h.parse::<f32>())
+      {
+        options.size = Some(egui::vec2(w, h));
+      }
+    }
+
+    Some(options)
+  }
+}
+
+/// Synthetic input queued from Lua with `push_input_event`, fed to egui on
+/// the next frame.
+#[derive(Default)]
+struct InputQueue(Vec<egui::Event>);
+
+fn modifiers_from_lua(table: Option<mlua::Table>) -> egui::Modifiers {
+  let Some(table) = table else {
+    return egui::Modifiers::default();
+  };
+  let ctrl = table.get::<bool>("ctrl").unwrap_or(false);
+  egui::Modifiers {
+    alt: table.get::<bool>("alt").unwrap_or(false),
+    ctrl,
+    shift: table.get::<bool>("shift").unwrap_or(false),
+    mac_cmd: false,
+    command: ctrl,
+  }
+}
+
+fn event_from_lua(table: mlua::Table) -> mlua::Result<egui::Event> {
+  let kind: String = table.get("type")?;
+  let pos = || -> mlua::Result<egui::Pos2> { Ok(egui::pos2(table.get("x")?, table.get("y")?)) };
+
+  Ok(match kind.as_str() {
+    "pointer_moved" => egui::Event::PointerMoved(pos()?),
+    "pointer_button" => egui::Event::PointerButton {
+      pos: pos()?,
+      button: match table.get::<Option<String>>("button")?.as_deref() {
+        Some("secondary") => egui::PointerButton::Secondary,
+        Some("middle") => egui::PointerButton::Middle,
+        _ => egui::PointerButton::Primary,
+      },
+      pressed: table.get("pressed")?,
+      modifiers: modifiers_from_lua(table.get("modifiers")?),
+    },
+    "text" => egui::Event::Text(table.get("text")?),
+    "key" => {
+      let name: String = table.get("key")?;
+      egui::Event::Key {
+        key: egui::Key::from_name(&name)
+          .ok_or_else(|| mlua::Error::RuntimeError(format!("unknown key name '{}'", name)))?,
+        physical_key: None,
+        pressed: table.get("pressed")?,
+        repeat: false,
+        modifiers: modifiers_from_lua(table.get("modifiers")?),
+      }
+    }
+    _ => {
+      return Err(mlua::Error::RuntimeError(format!(
+        "unknown input event type '{}'",
+        kind
+      )));
+    }
+  })
+}
+
+fn register_input(lua: &mlua::Lua) -> mlua::Result<()> {
+  lua.set_app_data(InputQueue::default());
+  lua.globals().set(
+    "push_input_event",
+    lua.create_function(|lua, table: mlua::Table| {
+      let event = event_from_lua(table)?;
+      if let Some(mut queue) = lua.app_data_mut::<InputQueue>() {
+        queue.0.push(event);
+      }
+      Ok(())
+    })?,
+  )
+}
+
+fn test_step(lua: &mlua::Lua) -> mlua::Result<mlua::Function> {
+  lua
+    .globals()
+    .get::<mlua::Table>("ui")?
+    .get::<mlua::Table>("test")?
+    .get::<mlua::Function>("step")
+}
+
+/// Drives the app without a window and returns the process exit status:
+/// the code given to `ui.exit`, `1` on the first Lua error, `0` otherwise.
+pub async fn run(lulu: &mut Lulu, entry: Option<&Path>, options: HeadlessOptions) -> i32 {
+  let window_conf = window::conf(lulu, entry);
+  let size = options
+    .size
+    .or_else(|| {
+      let size = window_conf.as_ref()?.get::<mlua::Table>("size").ok()?;
+      Some(egui::vec2(size.get(1).ok()?, size.get(2).ok()?))
+    })
+    .unwrap_or(egui::vec2(320.0, 240.0));
+
+  if let Err(err) = storage::install(&lulu.lua, None) {
+    eprintln!("{}", err);
+    return 1;
+  }
+
+  let (main, err) = ui::load(lulu);
+  let sources = Sources::collect(lulu, entry);
+
+  let ctx = egui::Context::default();
+  ui::install_fonts(&ctx);
+
+  let mut app = LuluUiApp::new(&ctx, lulu.clone(), main, err, sources);
+
+  if let Err(err) = register_input(&lulu.lua) {
+    eprintln!("{}", err);
+    return 1;
+  }
+
+  let step = if options.test {
+    match test_step(&lulu.lua) {
+      Ok(step) => Some(step),
+      Err(err) => {
+        eprintln!("{}", err);
+        return 1;
+      }
+    }
+  } else {
+    None
+  };
+
+  let start = Instant::now();
+  let mut frame: u64 = 0;
+
+  loop {
+    if let Some(step) = &step
+      && let Err(err) = step.call::<()>(())
+    {
+      eprintln!("{}", err);
+      return 1;
+    }
+
+    let events = lulu
+      .lua
+      .app_data_mut::<InputQueue>()
+      .map(|mut queue| std::mem::take(&mut queue.0))
+      .unwrap_or_default();
+
+    let input = egui::RawInput {
+      screen_rect: Some(egui::Rect::from_min_size(egui::Pos2::ZERO, size)),
+      time: Some(start.elapsed().as_secs_f64()),
+      predicted_dt: options.frame_time.as_secs_f32(),
+      events,
+      ..Default::default()
+    };
+
+    let _ = ctx.run(input, |ctx| app.frame(ctx));
+
+    if let Some(err) = app.error() {
+      eprintln!("{}", err);
+      return 1;
+    }
+
+    if let Some(code) = app.exit_code() {
+      return code;
+    }
+
+    frame += 1;
+    if options.frames > 0 && frame >= options.frames {
+      if options.test {
+        eprintln!("tests did not finish within {} frames", options.frames);
+        return 1;
+      }
+      return 0;
+    }
+
+    tokio::time::sleep(options.frame_time).await;
+  }
+}
@@ -2,13 +2,126 @@
 local id = 0
 local elements = Vec({})
 
+-- The component whose `prepare`, `build` or lifecycle hook is running. The
+-- subscriptions, tasks and timers it starts are stopped when it unmounts.
+local current_owner
+-- While a component builds, the derived states it creates. They are
+-- disposed once that build is replaced.
+local current_derived
+
+-- The states read by the `computed` or `effect` being evaluated.
+local current_reads
+
+-- The `Suspense`s being rendered, innermost last.
+local suspense_stack = {}
+
+local unmount
+
+local function own(kind, value)
+  if current_owner then
+    current_owner[kind] = current_owner[kind] or {}
+    table.insert(current_owner[kind], value)
+  end
+end
+
+-- Ties a derived state or effect to the build creating it, or else to the
+-- component whose `prepare` or hook is running.
+local function own_derived(value)
+  if current_derived then
+    table.insert(current_derived, value)
+  else
+    own("__subscriptions", value)
+  end
+end
+
+local batch_depth = 0
+local batch_queue = {}
+local batch_jobs = {}
+
+-- Runs `job(target)` now, or once when the current batch ends however many
+-- times it was scheduled until then.
+local function schedule(target, job)
+  if batch_depth == 0 then
+    job(target)
+  elseif not batch_jobs[target] then
+    batch_jobs[target] = job
+    table.insert(batch_queue, target)
+  end
+end
+
+-- Runs the scheduled jobs, including those scheduled while it runs. A job
+-- that raises leaves the rest queued for the next flush.
+local function flush_batch()
+  batch_depth = 1
+  local ok, err = pcall(function()
+    while #batch_queue > 0 do
+      local target = table.remove(batch_queue, 1)
+      local job = batch_jobs[target]
+      batch_jobs[target] = nil
+      job(target)
+    end
+  end)
+  batch_depth = 0
+  if not ok then
+    error(err, 0)
+  end
+end
+
+-- Calls `fn`, deferring scheduled jobs until the outermost batch ends. The
+-- depth is restored and the jobs flushed even when `fn` raises, and the
+-- error is raised again afterwards.
+local function batch(fn, ...)
+  batch_depth = batch_depth + 1
+  local ok, result = pcall(fn, ...)
+  if batch_depth == 1 then
+    flush_batch()
+  else
+    batch_depth = batch_depth - 1
+  end
+  if not ok then
+    error(result, 0)
+  end
+  return result
+end
+
+-- Calls `fn`, returning its result and the set of states it read.
+local function track(fn)
+  local outer = current_reads
+  current_reads = {}
+  local result = fn()
+  local reads = current_reads
+  current_reads = outer
+  return result, reads
+end
+
+local function repaint(after)
+  if request_repaint then
+    request_repaint(after)
+  end
+end
+
+class! Subscription(state, fn), {
+  unsubscribe(){
+    if not self.state then return end
+    for i, fn in ipairs(self.state._on_set) do
+      if fn == self.fn then
+        table.remove(self.state._on_set, i)
+        break
+      end
+    end
+    self.state = nil
+  }
+}
+
 class! @into_collectible("collect") State(@default_to("") value), {
   init(){
     self._on_set = {}
     self.on_set = function(val)
-      for _, f in ipairs(self._on_set) do
+      -- Copied so subscribers can unsubscribe while being notified.
+      for _, f in ipairs({ unpack(self._on_set) }) do
         f(val)
       end
+      repaint()
     end
   }
   format(str){
@@ -61,19 +174,51 @@ class! @into_collectible("collect") State(@default_to("") value), {
     end)
   }
   
+  -- Calls `fn(value)` every time the state is set, until the returned
+  -- subscription's `unsubscribe` is called.
+  subscribe(fn){
+    table.insert(self._on_set, fn)
+    return Subscription(self, fn)
+  }
+
+  -- A state that follows `formatter(value)`. This state only holds it
+  -- weakly, so a derived state nothing else uses is dropped along with its
+  -- subscription; `unsubscribe` stops it explicitly.
   map(formatter){
-    local s = State(formatter(self.value))
-    table.insert(self._on_set, function(val)
-      s:set(formatter(val))
+    local derived = State(formatter(self:get()))
+    local ref = setmetatable({ derived }, { __mode = "v" })
+    local subscription
+    subscription = self:subscribe(function(val)
+      if ref[1] then
+        ref[1]:set(formatter(val))
+      else
+        subscription:unsubscribe()
+      end
     end)
-    return s
+    derived._source = self
+    derived._subscription = subscription
+    own_derived(derived)
+    return derived
+  }
+
+  unsubscribe(){
+    if self._subscription then
+      self._subscription:unsubscribe()
+    end
+    return self
   }
 
   get(){
+    if current_reads then
+      current_reads[self] = true
+    end
     return self.value
   }
 
   set(val){
+    if val == self.value and type(val) ~= "table" then
+      return self
+    end
     self.value = val
     if self.on_set then
       self.on_set(val)
@@ -167,6 +312,235 @@ class! @into_collectible("collect") State(@default_to("") value), {
   }
 }
 
+-- Subscribes `on_change` to every state in `reads`, holding `target` weakly
+-- like `map` does.
+local function subscribe_all(reads, target, on_change)
+  local ref = setmetatable({ target }, { __mode = "v" })
+  local subscriptions = {}
+  for state in pairs(reads) do
+    local subscription
+    subscription = state:subscribe(function()
+      if ref[1] then
+        on_change(ref[1])
+      else
+        subscription:unsubscribe()
+      end
+    end)
+    table.insert(subscriptions, subscription)
+  end
+  return subscriptions
+end
+
+local function unsubscribe_all(subscriptions)
+  for _, subscription in ipairs(subscriptions or {}) do
+    subscription:unsubscribe()
+  end
+end
+
+-- A state whose value is `fn()`, recomputed when a state read by `fn`
+-- changes. It is recomputed right away when something subscribes to it and
+-- on the next `get` otherwise.
+class! Computed:State, {
+  init(fn){
+    self._compute = fn
+    self._dirty = true
+    self._deps = {}
+    self.value = nil
+  }
+
+  get(){
+    if current_reads then
+      current_reads[self] = true
+    end
+    if self._dirty then
+      unsubscribe_all(self._deps)
+      local value, reads = track(self._compute)
+      self._deps = subscribe_all(reads, self, function(computed)
+        computed:invalidate()
+      end)
+      self.value = value
+      self._dirty = false
+    end
+    return self.value
+  }
+
+  invalidate(){
+    self._dirty = true
+    if #self._on_set > 0 then
+      self.on_set(self:get())
+    else
+      repaint()
+    end
+  }
+
+  set(){
+    error("a computed state can not be set", 2)
+  }
+
+  unsubscribe(){
+    unsubscribe_all(self._deps)
+    self._deps = {}
+    self._dirty = true
+    return self
+  }
+}
+
+-- Effects that are still running, so they are not collected while only the
+-- states they read refer to them.
+local running_effects = {}
+
+-- Runs `fn` now and again whenever a state it read changes, until
+-- `unsubscribe` is called. A function returned by `fn` is called before the
+-- next run and on `unsubscribe`.
+class! Effect(fn), {
+  init(){
+    self._deps = {}
+    running_effects[self] = true
+    self:run()
+  }
+
+  run(){
+    if self._running or self._stopped then return end
+    self._running = true
+    self:_cleanup()
+    local cleanup, reads = track(self.fn)
+    self._on_cleanup = type(cleanup) == "function" and cleanup or nil
+    self._deps = subscribe_all(reads, self, function(effect)
+      schedule(effect, effect.run)
+    end)
+    self._running = false
+  }
+
+  _cleanup(){
+    unsubscribe_all(self._deps)
+    self._deps = {}
+    if self._on_cleanup then
+      local cleanup = self._on_cleanup
+      self._on_cleanup = nil
+      cleanup()
+    end
+  }
+
+  unsubscribe(){
+    self._stopped = true
+    running_effects[self] = nil
+    self:_cleanup()
+    return self
+  }
+}
+
+-- Shared states and the actions that change them. Each field of `initial`
+-- becomes a `State` on the store, and each action is callable as a method
+-- that runs batched:
+--
+--   local counter = Store({ count = 0 }, {
+--     add = function(store, n) store.count:add(n) end,
+--   })
+--   counter:add(1)
+--
+-- `subscribe(fn)` calls `fn(store)` once per batch in which any of its
+-- states changed.
+class! Store(@default_to({}) initial, @default_to({}) actions), {
+  init(){
+    self._on_set = {}
+    self.states = {}
+    for name, value in pairs(self.initial) do
+      local state = instanceof(value, State) and value or State(value)
+      self[name] = state
+      table.insert(self.states, name)
+      state:subscribe(function()
+        schedule(self, self.notify)
+      end)
+    end
+    for name, action in pairs(self.actions) do
+      self[name] = function(store, ...)
+        return batch(action, store, ...)
+      end
+    end
+  }
+
+  subscribe(fn){
+    table.insert(self._on_set, fn)
+    return Subscription(self, fn)
+  }
+
+  notify(){
+    for _, fn in ipairs({ unpack(self._on_set) }) do
+      fn(self)
+    end
+  }
+
+  -- The current value of every state, by name.
+  snapshot(){
+    local values = {}
+    for _, name in ipairs(self.states) do
+      values[name] = self[name]:get()
+    end
+    return values
+  }
+}
+
+-- A state holding `{ loading, value, error }` for `fn(...)` run as an
+-- `async` task. The task belongs to the component creating the resource,
+-- and `refetch` runs it again, keeping the last value until it finishes.
+class! Resource:State, {
+  init(fn, ...){
+    self._fetch = fn
+    self._args = { ... }
+    self._owner = current_owner
+    self._generation = 0
+    self.value = { loading = true }
+    self:refetch()
+  }
+
+  get(){
+    local suspense = suspense_stack[#suspense_stack]
+    if suspense then
+      suspense:track(self)
+    end
+    return State.get(self)
+  }
+
+  refetch(){
+    self._generation = self._generation + 1
+    local generation = self._generation
+    self:set({ loading = true, value = self.value.value })
+
+    local owner = current_owner
+    current_owner = self._owner
+    local task
+    task = async(function()
+      return self._fetch(unpack(self._args))
+    end):catch(function() end):after(function(value)
+      -- A newer fetch replaces the result of this one.
+      if self._generation ~= generation then return end
+      if task.error then
+        self:set({ loading = false, error = task.error })
+      else
+        self:set({ loading = false, value = value })
+      end
+    end)
+    current_owner = owner
+    return self
+  }
+}
+
+function resource(fn, ...)
+  return Resource(fn, ...)
+end
+
+function computed(fn)
+  local state = Computed(fn)
+  own_derived(state)
+  return state
+end
+
+function effect(fn)
+  local running = Effect(fn)
+  own_derived(running)
+  return running
+end
+
 function remove_node_from(children, node)
   children:remove(function(child)
     if type(node) == "number" then
@@ -285,10 +659,11 @@ class! @into_collectible("collect") Node(@default_to("") #name, @default_to(Vec(
   }
 
   remove(){
+    unmount(self)
     if self.parent == "root" then
-      remove_node_from(elements, node)
-    else
-      self.parent.remove_node(self)
+      remove_node_from(elements, self)
+    elseif self.parent then
+      self.parent:remove_node(self)
     end
   }
 
@@ -327,7 +702,7 @@ class! Widget:Node, {
     for k, v in pairs(self.props.__real) do
       if k:sub(1, 3) == "on_" then
         local event = k:sub(4, #k)
-        self:on(event, v)
+        self._event_handlers:push({ event = event, fn = v, from_props = true })
         self.props[k] = nil
       end
     end
@@ -429,6 +804,13 @@ end
 
 ui = {}
 
+-- Runs `fn` with rebuilds and effects held back until it returns, so each
+-- component rebuilds once however many of its states `fn` sets. Event
+-- handlers already run this way.
+function ui.batch(fn)
+  batch(fn)
+end
+
 local function get_value(val)
   if val ~= nil and type(val) == "table" and val.__value then
     return val.__value
@@ -437,9 +819,32 @@ local function get_value(val)
   end
 end
 
+-- The `ErrorBoundary`s being rendered, innermost last.
+local boundary_stack = {}
+
+local function emit_event(self, event, payload)
+  -- The handler is traced inside the batch, since `batch` re-raises its
+  -- error from outside the handler's frames.
+  local ok, err = true, nil
+  local flushed, flush_err = xpcall(batch, debug.traceback, function()
+    ok, err = xpcall(self.emit, debug.traceback, self, event, payload)
+  end)
+  if ok and not flushed then
+    ok, err = false, flush_err
+  end
+  if not ok then
+    local boundary = boundary_stack[#boundary_stack]
+    if boundary then
+      boundary:catch(err)
+    else
+      _G.ui.report_error("event", err)
+    end
+  end
+end
+
 local function handle_events(ui, self, event, response)
   if response[event] then
-    self:emit(event, {
+    emit_event(self, event, {
       value = get_value(response.value),
       keydown = function(key) return ui:keydown(key) end,
       keypressed = function(key) return ui:keypressed(key) end,
@@ -461,8 +866,22 @@ local function handle_change(self, name, response)
   return response
 end
 
+local render_from
+
+-- Renders a tooltip or menu: a node, or a function called with the `ui`
+-- that may return one.
+local function render_contents(contents, ui)
+  if type(contents) == "function" then
+    contents = contents(ui)
+  end
+  if instanceof(contents, Node) then
+    render_from(Vec({ contents }), ui)
+  end
+end
+
 local function handle_reponse(ui, self, response)
   if not response then return end
+  self.response = response
   self.is_pointer_button_down_on = response.is_pointer_button_down_on
   self.drag_delta = response.drag_delta
   self.contains_pointer = response.contains_pointer
@@ -497,10 +916,22 @@ local function handle_reponse(ui, self, response)
     response:scroll_to_me()
   end
 
+  local tooltip = self.props.tooltip
+  if type(tooltip) == "string" or type(tooltip) == "number" then
+    response:on_hover_text(tostring(tooltip))
+  elseif tooltip then
+    response:on_hover_ui(function(ui) render_contents(tooltip, ui) end)
+  end
+
+  local menu = self.props.context_menu
+  if menu then
+    response:context_menu(function(ui) render_contents(menu, ui) end)
+  end
+
   return response
 end
 
-local function render_from(vec, ui)
+function render_from(vec, ui)
   if not vec then return end
   vec:for_each(function(v)
     if not v then return end
@@ -668,6 +1099,191 @@ ui.VBoxCenterJustified = register_element("vbox_center_justified", {}, function(
   end)
 end)
 
+-- The values provided by the `Provider`s being rendered, innermost last.
+local context_stack = {}
+
+-- The value a `Provider` above the component being built provides under
+-- `key`, or `default` when there is none. Meant for `build`, which runs while
+-- its ancestors render.
+function use_context(key, default)
+  for i = #context_stack, 1, -1 do
+    local value = context_stack[i][key]
+    if value ~= nil then
+      return value
+    end
+  end
+  return default
+end
+
+-- Provides `values` (or a single `value` under `context`) to every
+-- descendant through `use_context`. States and stores are passed as is.
+ui.Provider = register_element("provider", { values = {} }, function(self, ui)
+  local props = self.props.__real
+  local values = {}
+  for k, v in pairs(props.values) do
+    values[k] = v
+  end
+  if props.context ~= nil then
+    values[props.context] = props.value
+  end
+
+  table.insert(context_stack, values)
+  render_from(self.children, ui)
+  table.remove(context_stack)
+end)
+
+local function render_fallback(self, ui)
+  local err = tostring(self.error)
+  local reset = function() self:reset() end
+  if self.props.fallback then
+    -- A returned node is kept until reset so it can hold state and events.
+    if not self.__built then
+      local node = self.props.fallback(err, reset, ui)
+      if instanceof(node, Node) then
+        self.__built = node
+      end
+    end
+    if self.__built then
+      render_from(Vec({ self.__built }), ui)
+    end
+    return
+  end
+
+  ui:frame_block({
+    color = { 90, 25, 25, 1 },
+    stroke = { 200, 70, 70, 1, 1 },
+    rounding = 4,
+    padding = 8,
+  }, function(ui)
+    ui:colored_label(err:match("^(.-)\n") or err, { 255, 200, 200, 1 })
+    if ui:button("Retry").clicked then
+      reset()
+    end
+  end)
+end
+
+-- Renders its children until building, rendering or an event handler in
+-- them raises an error, then renders `fallback(err, reset, ui)` (or a red
+-- panel with the message) in their place until `reset` is called.
+ui.ErrorBoundary = register_element("error_boundary", {}, function(self, ui)
+  if self.error == nil then
+    local contexts, suspenses = #context_stack, #suspense_stack
+    local owner, derived, reads = current_owner, current_derived, current_reads
+
+    table.insert(boundary_stack, self)
+    local ok, err = xpcall(render_from, debug.traceback, self.children, ui)
+    table.remove(boundary_stack)
+
+    if not ok then
+      while #context_stack > contexts do
+        table.remove(context_stack)
+      end
+      while #suspense_stack > suspenses do
+        table.remove(suspense_stack)
+      end
+      current_owner, current_derived, current_reads = owner, derived, reads
+      self:catch(err)
+    end
+  end
+
+  if self.error ~= nil then
+    render_fallback(self, ui)
+  end
+end, function(ErrorBoundary)
+
+  function ErrorBoundary:catch(err)
+    if self.error ~= nil then return end
+    self.error = err
+    local ok, handler_err = xpcall(function()
+      self:emit("error", { value = err })
+    end, debug.traceback)
+    if not ok then
+      _G.ui.report_error("event", handler_err)
+    end
+    repaint()
+  end
+
+  function ErrorBoundary:reset()
+    unmount(self.__built)
+    self.__built = nil
+    self.error = nil
+    repaint()
+    return self
+  end
+end)
+
+-- Marks the components of a tree to build again, without going into the
+-- trees they build, which are rebuilt along with them.
+local function rebuild_components(node)
+  if instanceof(node, Vec) then
+    node:for_each(rebuild_components)
+  elseif instanceof(node, Node) then
+    if node.build then
+      node.__built = nil
+    else
+      rebuild_components(node.children)
+    end
+  end
+end
+
+-- Renders `fallback` (a node, or a function returning one; a `Spinner` by
+-- default) instead of its children while a `resource` they read is
+-- loading, then builds the children again once every one has settled.
+ui.Suspense = register_element("suspense", {}, function(self, ui)
+  if not self._suspended then
+    table.insert(suspense_stack, self)
+    -- What the children drew is dropped if a resource they read is loading,
+    -- so the fallback shows from the frame it started.
+    local shown = ui:tentative(function(ui)
+      render_from(self.children, ui)
+    end, function()
+      return not self:pending()
+    end)
+    table.remove(suspense_stack)
+    if shown then return end
+    self._suspended = true
+  end
+
+  if not self.__built then
+    local fallback = self.props.fallback
+    if type(fallback) == "function" then
+      fallback = fallback()
+    end
+    self.__built = fallback or ui.Spinner {}
+  end
+  render_from(Vec({ self.__built }), ui)
+end, function(Suspense)
+
+  function Suspense:track(resource)
+    self._resources = self._resources or {}
+    if self._resources[resource] then return end
+    self._resources[resource] = true
+    self.__subscriptions = self.__subscriptions or {}
+    table.insert(self.__subscriptions, resource:subscribe(function()
+      self:settle()
+    end))
+  end
+
+  function Suspense:pending()
+    for resource in pairs(self._resources or {}) do
+      if resource.value.loading then
+        return true
+      end
+    end
+    return false
+  end
+
+  function Suspense:settle()
+    local pending = self:pending()
+    if self._suspended and not pending then
+      unmount(self.__built)
+      self.__built = nil
+      rebuild_components(self.children)
+    end
+    self._suspended = pending
+  end
+end)
+
 ui.Style = register_element("style", {}, function(self, ui)
   handle_style(self, ui)
   render_from(self.children, ui)
@@ -679,82 +1295,1009 @@ ui.Frame = register_element("frame", { style = {} }, function(self, ui)
   end)
 end)
 
-ui.Scope = register_element("scope", { render = function(ui) end }, function(self, ui)
-  ui:scope(function(ui)
-    self.props.render(ui)
+ui.Viewport = register_element("viewport", { title = "Viewport", open = true, deferred = false }, function(self, ui)
+  if not self.props.open then return end
+  local open = ui:viewport(self.props.id or ("viewport" .. self.id), self.props, function(ui)
+    render_from(self.children, ui)
   end)
+  if not open then
+    self.props.open = false
+    self:emit("close", {})
+  end
 end)
 
-ui.Handle = register_element("handle", { render = function(ui) end }, function(self, ui)
-  self.props.render(ui)
+-- `side` is "left" or "right". The width the user drags it to is kept
+-- across runs under `id`, and `open = false` collapses it.
+ui.SidePanel = register_element("side_panel", { side = "left", open = true }, function(self, ui)
+  ui:side_panel(self.props.id or ("side_panel" .. self.id), {
+    side = self.props.side,
+    open = self.props.open,
+    resizable = self.props.resizable,
+    show_separator_line = self.props.show_separator_line,
+    default_width = self.props.default_width,
+    min_width = self.props.min_width,
+    max_width = self.props.max_width,
+    exact_width = self.props.exact_width,
+  }, function(ui)
+    render_from(self.children, ui)
+  end)
 end)
 
-ui.Painter = register_element("painter", { render = function(ui) end }, function(self, ui)
-  local painter = ui:painter()
-  local renderfn = self.props.render
-  setfenv(renderfn, self.props)
-  renderfn(painter, ui)
+-- `side` is "top" or "bottom", for menu and status bars.
+ui.TopBottomPanel = register_element("top_bottom_panel", { side = "top", open = true }, function(self, ui)
+  ui:top_bottom_panel(self.props.id or ("top_bottom_panel" .. self.id), {
+    side = self.props.side,
+    open = self.props.open,
+    resizable = self.props.resizable,
+    show_separator_line = self.props.show_separator_line,
+    default_height = self.props.default_height,
+    min_height = self.props.min_height,
+    max_height = self.props.max_height,
+    exact_height = self.props.exact_height,
+  }, function(ui)
+    render_from(self.children, ui)
+  end)
 end)
 
-ui.Each = register_element("each", { items = {}, render = function(ui) end }, function(self, ui)
-  local items = get_prop_val(self.props.items)
+-- Takes the space left by the panels before it.
+ui.CentralPanel = register_element("central_panel", {}, function(self, ui)
+  ui:central_panel(function(ui)
+    render_from(self.children, ui)
+  end)
+end)
 
-  local function render_item(item, index, array)
-    local returns = self.props.render(item, index, array, ui)
-    if returns then
-      render_from(Vec({returns}), ui)
-    end
+-- Emits "clicked" when clicked or when its `shortcut` (like "Ctrl+S") is
+-- pressed while the menu bar holding it is shown, then closes the menu
+-- unless `keep_open` is set. Items given `checked` toggle it; items with
+-- `enabled = false` are greyed out and ignore their shortcut.
+ui.MenuItem = register_element("menu_item", { text = "", enabled = true }, function(self, ui)
+  local response = ui:menu_item(get_prop_val(self.props.text), {
+    shortcut = self.props.shortcut,
+    enabled = self.props.enabled,
+    checked = self.props.checked,
+  })
+  if response.clicked then
+    self:toggle()
+  end
+  handle_reponse(ui, self, response)
+  if response.clicked and not self.props.keep_open then
+    ui:close_menu()
   end
+end, function(MenuItem)
 
-  if instanceof(items, Vec) then
-    items:for_each(render_item)
-  elseif type(items) == "table" then
-    for k, v in ipairs(items) do
-      render_item(v, k, items)
+  function MenuItem:toggle()
+    if self.props.checked ~= nil then
+      self.props.checked = not self.props.checked
     end
   end
-end)
 
-
-ui.VList = register_element("vlist", { items = {}, render = function() end }, function(self, ui)
-  if not self._init then
-    self:rerender(self.props.items)
-    self._init = true
+  function MenuItem:activate()
+    self:toggle()
+    emit_event(self, "clicked", {})
   end
-  render_from(self.children, ui)
-end, function(VList)
+end)
 
-  function VList:init()
-    if not instanceof(self.props.__real.items, State) then
-      local items = self.props.__real.items
-      if not instanceof(items, Vec) then
-        items = Vec(items)
-      end
-      self.props.__real.items = State(items)
-    end
+-- A menu, or a submenu when inside another one.
+ui.Menu = register_element("menu", { text = "" }, function(self, ui)
+  ui:menu_button(get_prop_val(self.props.text), function(ui)
+    render_from(self.children, ui)
+  end)
+end)
 
-    if type(self.props.render) ~= "function" then
-      self.props.render = function() end
+local function collect_shortcuts(node, items)
+  if instanceof(node, Vec) then
+    node:for_each(function(child) collect_shortcuts(child, items) end)
+  elseif instanceof(node, Node) then
+    if node.name == "menu_item" and node.props.shortcut and node.props.enabled then
+      table.insert(items, node)
     end
+    collect_shortcuts(node.children, items)
+    collect_shortcuts(node.__built, items)
+  end
+  return items
+end
 
-    self.props.__real.items:map(function(i)
-      self:rerender(i)
-    end)
+local function shortcut_keys(item)
+  local _, count = item.props.shortcut:gsub("%+", "")
+  return count
+end
 
-    self._init = false
+-- Lays out its `Menu`s in a row, usually in a top `TopBottomPanel`, and
+-- runs the shortcuts of the items in them.
+ui.MenuBar = register_element("menu_bar", {}, function(self, ui)
+  local items = collect_shortcuts(self.children, {})
+  -- Extra modifiers are ignored when matching, so "Ctrl+Shift+S" has to be
+  -- tried before "Ctrl+S".
+  table.sort(items, function(a, b) return shortcut_keys(a) > shortcut_keys(b) end)
+  for _, item in ipairs(items) do
+    if ui:consume_shortcut(item.props.shortcut) then
+      item:activate()
+    end
   end
 
-  function VList:rerender(items)
+  ui:menu_bar(function(ui)
+    render_from(self.children, ui)
+  end)
+end)
+
+-- Renders `anchor`, and its children below it while the popup is open.
+-- Without an `open` prop clicking the anchor toggles it; with one, it is
+-- written back `false` when the popup closes on Escape or a click elsewhere.
+ui.Popup = register_element("popup", {}, function(self, ui)
+  local anchor = self.props.anchor
+  if not anchor then return end
+  render_from(Vec({ anchor }), ui)
+  if not anchor.response then return end
+
+  local open = ui:popup_below_widget(self.props.id or ("popup" .. self.id), anchor.response, self.props.open, function(ui)
+    render_from(self.children, ui)
+  end)
+  if open ~= (self._open or false) then
+    self._open = open
+    if not open and self.props.open then
+      self.props.open = false
+    end
+    self:emit(open and "open" or "close", {})
+  end
+end)
+
+-- Renders its children, under `title` if given, in a dialog above the rest
+-- of the UI, which is dimmed and takes no input while it is `open`. Unless
+-- `closable` is false, Escape or a click outside closes it; with modals
+-- opened over each other only the topmost gets keys and closes on Escape.
+ui.Modal = register_element("modal", { open = true, closable = true, dim = 0.5 }, function(self, ui)
+  if not self.props.open then return end
+  local outside, escape = ui:modal(self.props.id or ("modal" .. self.id), { dim = self.props.dim }, function(ui)
+    if self.props.title then
+      ui:heading(self.props.title)
+    end
+    render_from(self.children, ui)
+  end)
+  if self.props.closable and (outside or escape) then
+    self:close()
+  end
+end, function(Modal)
+
+  function Modal:close()
+    self.props.open = false
+    self:emit("close", {})
+  end
+end)
+
+-- The `ui.confirm` dialogs waiting for an answer, shown above the app.
+local dialogs = {}
+
+local function render_dialogs(ui)
+  if #dialogs > 0 then
+    render_from(Vec({ unpack(dialogs) }), ui)
+  end
+end
+
+local function in_task()
+  local co = coroutine.running()
+  for _, fut in ipairs(__future_stack) do
+    if fut.co == co then
+      return true
+    end
+  end
+  return false
+end
+
+-- Asks the user to pick one of `buttons` ({ "OK", "Cancel" } by default) in
+-- a modal dialog. Inside an `async` task it waits for and returns the label
+-- of the chosen button, or nil if the dialog was dismissed (which
+-- `closable = false` prevents); anywhere else it returns a Future of it.
+--
+--   async(function()
+--     if ui.confirm{ title = "Quit", message = "Discard changes?" } == "OK" then
+--       ui.exit()
+--     end
+--   end)
+function ui.confirm(options)
+  options = options or {}
+  local answered, chosen = false, nil
+  local dialog
+
+  local function answer(label)
+    if answered then return end
+    answered, chosen = true, label
+    for i, open in ipairs(dialogs) do
+      if open == dialog then
+        table.remove(dialogs, i)
+        break
+      end
+    end
+    unmount(dialog)
+    repaint()
+  end
+
+  local buttons = {}
+  for _, label in ipairs(options.buttons or { "OK", "Cancel" }) do
+    table.insert(buttons, ui.Button { text = label, on_clicked = function() answer(label) end })
+  end
+  dialog = ui.Modal {
+    title = options.title,
+    closable = options.closable,
+    on_close = function() answer(nil) end,
+    children = {
+      ui.Label { text = options.message or "" },
+      ui.HBox { children = buttons },
+    }
+  }
+  table.insert(dialogs, dialog)
+  repaint()
+
+  local function wait()
+    while not answered do
+      coroutine.yield(ASYNC_POLL_PENDING)
+    end
+    return chosen
+  end
+
+  if in_task() then
+    return wait()
+  end
+  return async(wait)
+end
+
+ui.Scope = register_element("scope", { render = function(ui) end }, function(self, ui)
+  ui:scope(function(ui)
+    self.props.render(ui)
+  end)
+end)
+
+ui.Handle = register_element("handle", { render = function(ui) end }, function(self, ui)
+  self.props.render(ui)
+end)
+
+ui.Painter = register_element("painter", { render = function(ui) end }, function(self, ui)
+  local painter = ui:painter()
+  local renderfn = self.props.render
+  setfenv(renderfn, self.props)
+  renderfn(painter, ui)
+end)
+
+ui.Each = register_element("each", { items = {}, render = function(ui) end }, function(self, ui)
+  local items = get_prop_val(self.props.items)
+
+  local function render_item(item, index, array)
+    local returns = self.props.render(item, index, array, ui)
+    if returns then
+      render_from(Vec({returns}), ui)
+    end
+  end
+
+  if instanceof(items, Vec) then
+    items:for_each(render_item)
+  elseif type(items) == "table" then
+    for k, v in ipairs(items) do
+      render_item(v, k, items)
+    end
+  end
+end)
+
+
+ui.VList = register_element("vlist", { items = {}, render = function() end }, function(self, ui)
+  if not self._init then
+    self:rerender(self.props.items)
+    self._init = true
+  end
+  render_from(self.children, ui)
+end, function(VList)
+
+  function VList:init()
+    if not instanceof(self.props.__real.items, State) then
+      local items = self.props.__real.items
+      if not instanceof(items, Vec) then
+        items = Vec(items)
+      end
+      self.props.__real.items = State(items)
+    end
+
+    if type(self.props.render) ~= "function" then
+      self.props.render = function() end
+    end
+
+    self.__subscriptions = {
+      self.props.__real.items:subscribe(function(i)
+        self:rerender(i)
+      end)
+    }
+
+    self._init = false
+  end
+
+  function VList:rerender(items)
     self.children = items:map(function(item, index, array)
       return self.props.render(item, index, array)
     end)
   end
 end)
 
+-- Only the rows in view call `render(item, index, items, ui)`. A row keeps
+-- the node it returned while it stays in view and its item is unchanged, and
+-- unmounts it after; `key` (a field name or `function(item, index)`) matches
+-- rows to their items when items are inserted or removed.
+ui.VirtualList = register_element("virtual_list", { items = {}, render = function() end }, function(self, ui)
+  local items = get_prop_val(self.props.items)
+  if instanceof(items, Vec) then
+    items = items.items
+  end
+
+  local key = self.props.key
+  local function key_of(index)
+    local item = items[index]
+    if type(key) == "function" then
+      return key(item, index)
+    elseif key ~= nil and type(item) == "table" then
+      return item[key]
+    end
+    return index
+  end
+
+  local cached = self._rows or {}
+  local shown = {}
+  ui:virtual_list(self.props.id, {
+    rows = #items,
+    row_height = self.props.row_height,
+    estimated_row_height = self.props.estimated_row_height,
+    max_height = self.props.max_height,
+    auto_shrink = self.props.auto_shrink,
+    stick_to_bottom = self.props.stick_to_bottom,
+    scroll_to = self._dunders.scroll_to,
+    key = key ~= nil and function(index) return tostring(key_of(index)) end or nil,
+  }, function(ui, index)
+    local item = items[index]
+    local k = key_of(index)
+    local row = cached[k]
+    if not row or row.item ~= item then
+      local node = self.props.render(item, index, items, ui)
+      row = node and { item = item, nodes = Vec({ node }) }
+    end
+    if row then
+      shown[k] = row
+      render_from(row.nodes, ui)
+    end
+  end)
+
+  self._dunders.scroll_to = nil
+  -- Rows out of view, or built again for a changed item, are done with
+  for k, row in pairs(cached) do
+    if shown[k] ~= row then
+      unmount(row.nodes)
+    end
+  end
+  self._rows = shown
+end, function(VirtualList)
+
+  function VirtualList:scroll_to(index)
+    self._dunders.scroll_to = index
+    return self
+  end
+end)
+
+-- `rows` is a list (or Vec, or State of either) of items, and each column
+-- either shows `item[column.key]` or calls `column.render(ui, item, row)`.
+-- Only the rows in view are rendered. `selected` and `sort_column` are kept
+-- by the table itself unless they are passed in.
+ui.Table = register_element("table", {
+  rows = {},
+  columns = {},
+  row_height = 18,
+  striped = true,
+  resizable = true,
+}, function(self, ui)
+  local items = get_prop_val(self.props.rows)
+  if instanceof(items, Vec) then
+    items = items.items
+  end
+  local columns = get_prop_val(self.props.columns)
+
+  local options = {
+    rows = #items,
+    columns = columns,
+    row_height = self.props.row_height,
+    header_height = self.props.header_height,
+    max_height = self.props.max_height,
+    striped = self.props.striped,
+    resizable = self.props.resizable,
+    selected = tonumber(self.props.selected),
+    sort_column = self.props.sort_column,
+    sort_ascending = self.props.sort_ascending,
+    scroll_to_row = self.props.scroll_to_row,
+  }
+
+  local result = ui:table(self.props.id, options, function(ui, row, col)
+    local item = items[row]
+    local column = columns[col]
+    if column.render then
+      local returns = column.render(ui, item, row)
+      if returns then
+        render_from(Vec({returns}), ui)
+      end
+    elseif column.key ~= nil and item ~= nil then
+      ui:label(tostring(item[column.key]))
+    end
+  end)
+
+  if result.sort then
+    if self.props.sort_column ~= nil then
+      self.props.sort_column = result.sort
+      self.props.sort_ascending = result.ascending
+    end
+    emit_event(self, "sort", {
+      value = columns[result.sort].key or result.sort,
+      column = result.sort,
+      ascending = result.ascending
+    })
+  end
+
+  local row = result.double_clicked or result.clicked
+  if row then
+    if self.props.selected ~= nil then
+      self.props.selected = row
+    end
+    emit_event(self, "select", { value = row, item = items[row] })
+    if result.double_clicked then
+      emit_event(self, "activate", { value = row, item = items[row] })
+    end
+  end
+end)
+
+-- The tabs of `Tabs` and `Dock` children, which are titled by their
+-- `title` prop and keyed by their `key` prop or else their position.
+local function child_tabs(self, closable)
+  local tabs, nodes = {}, {}
+  for i, child in ipairs(self.children.items) do
+    if instanceof(child, Node) then
+      local key = tostring(child.props.key or i)
+      local child_closable = child.props.closable
+      if child_closable == nil then
+        child_closable = closable
+      end
+      table.insert(tabs, { key = key, title = tostring(child.props.title or key), closable = child_closable })
+      nodes[key] = child
+    end
+  end
+  return tabs, nodes
+end
+
+local function index_of(list, value)
+  for i, item in ipairs(list) do
+    if item == value then
+      return i
+    end
+  end
+end
+
+-- The key of the tab next to `key`, preferring the one after it.
+local function neighbour_tab(tabs, key)
+  for i, tab in ipairs(tabs) do
+    if tab.key == key then
+      local next_tab = tabs[i + 1] or tabs[i - 1]
+      return next_tab and next_tab.key
+    end
+  end
+end
+
+-- Removes the child behind tab `key` and emits "close".
+local function close_tab(self, key)
+  local _, nodes = child_tabs(self)
+  local node = nodes[key]
+  if node then
+    remove_node_from(self.children, node)
+    unmount(node)
+  end
+  emit_event(self, "close", { value = key })
+  repaint()
+end
+
+-- Shows the child whose key `selected` holds under a row of tabs. Tabs can
+-- be dragged along the row, which reorders the children and emits
+-- "reorder" with the keys in their new order, and `closable` ones (or
+-- children with `closable = true`) are closed with their × button or a
+-- middle click, which removes them and emits "close".
+ui.Tabs = register_element("tabs", { closable = false, reorderable = true }, function(self, ui)
+  local tabs, nodes = child_tabs(self, self.props.closable)
+  if #tabs == 0 then return end
+
+  local selected = self.props.selected
+  if selected == nil or selected == "" or not nodes[tostring(selected)] then
+    selected = tabs[1].key
+  end
+  selected = tostring(selected)
+
+  local result = ui:tab_bar(self.props.id or ("tabs" .. self.id), tabs, {
+    selected = selected,
+    draggable = self.props.reorderable,
+  })
+  ui:separator()
+
+  if result.selected and result.selected ~= selected then
+    self:select(result.selected)
+    selected = result.selected
+  end
+  if result.dropped and nodes[result.dropped.key] then
+    self:move(result.dropped.key, result.dropped.index)
+  end
+
+  render_from(Vec({ nodes[selected] }), ui)
+
+  if result.closed then
+    if result.closed == selected and neighbour_tab(tabs, selected) then
+      self:select(neighbour_tab(tabs, selected))
+    end
+    close_tab(self, result.closed)
+  end
+end, function(Tabs)
+
+  function Tabs:select(key)
+    self.props.selected = key
+    emit_event(self, "select", { value = key })
+    return self
+  end
+
+  -- Moves tab `key` to 1-based `index`.
+  function Tabs:move(key, index)
+    local items = self.children.items
+    local _, nodes = child_tabs(self)
+    local from = index_of(items, nodes[key])
+    if not from then return self end
+    table.remove(items, from)
+    if index > from then
+      index = index - 1
+    end
+    table.insert(items, math.min(index, #items + 1), nodes[key])
+
+    local keys = {}
+    for _, tab in ipairs((child_tabs(self))) do
+      table.insert(keys, tab.key)
+    end
+    emit_event(self, "reorder", { value = keys })
+    return self
+  end
+
+  function Tabs:close(key)
+    key = tostring(key)
+    local neighbour = neighbour_tab((child_tabs(self)), key)
+    if key == tostring(self.props.selected) and neighbour then
+      self:select(neighbour)
+    end
+    close_tab(self, key)
+    return self
+  end
+end)
+
+local function copy_layout(node)
+  if type(node) ~= "table" then
+    return node
+  end
+  local copy = {}
+  for k, v in pairs(node) do
+    copy[k] = copy_layout(v)
+  end
+  return copy
+end
+
+local function layout_leaves(node, leaves)
+  if node.split then
+    for _, child in ipairs(node.children) do
+      layout_leaves(child, leaves)
+    end
+  else
+    table.insert(leaves, node)
+  end
+  return leaves
+end
+
+-- Drops empty groups, and splits left with a single group.
+local function prune_layout(node)
+  if node.split then
+    local children = {}
+    for _, child in ipairs(node.children) do
+      child = prune_layout(child)
+      if child then
+        table.insert(children, child)
+      end
+    end
+    if #children < 2 then
+      return children[1]
+    end
+    node.children = children
+    return node
+  end
+  if #node.tabs == 0 then
+    return nil
+  end
+  if not index_of(node.tabs, node.selected) then
+    node.selected = node.tabs[1]
+  end
+  return node
+end
+
+-- Removes tab `key` from every group but `keep`.
+local function take_tab(node, key, keep)
+  for _, leaf in ipairs(layout_leaves(node, {})) do
+    local index = index_of(leaf.tabs, key)
+    if leaf ~= keep and index then
+      table.remove(leaf.tabs, index)
+    end
+  end
+end
+
+-- The layout with each child in exactly one group, or nil when `layout`
+-- already is.
+local function fix_layout(layout, tabs, nodes)
+  local seen, missing, stale = {}, {}, false
+  for _, leaf in ipairs(layout_leaves(layout, {})) do
+    for _, key in ipairs(leaf.tabs) do
+      if not nodes[key] or seen[key] then
+        stale = true
+      end
+      seen[key] = true
+    end
+  end
+  for _, tab in ipairs(tabs) do
+    if not seen[tab.key] then
+      table.insert(missing, tab.key)
+    end
+  end
+  if not stale and #missing == 0 then
+    return nil
+  end
+
+  layout = copy_layout(layout)
+  local placed = {}
+  for _, leaf in ipairs(layout_leaves(layout, {})) do
+    local keys = {}
+    for _, key in ipairs(leaf.tabs) do
+      if nodes[key] and not placed[key] then
+        placed[key] = true
+        table.insert(keys, key)
+      end
+    end
+    leaf.tabs = keys
+  end
+  local first = layout_leaves(layout, {})[1]
+  for _, key in ipairs(missing) do
+    table.insert(first.tabs, key)
+  end
+  return prune_layout(layout) or { tabs = {} }
+end
+
+-- Where a dock action applies: the group at `path`, a string of the child
+-- indices leading to it from the root.
+local function layout_at(layout, path)
+  local node = layout
+  for index in path:gmatch("%d+") do
+    node = node.children[tonumber(index)]
+  end
+  return node
+end
+
+-- Applies what was done to a dock this frame to a copy of `layout`.
+local function apply_dock_action(layout, action)
+  layout = copy_layout(layout)
+  local target = layout_at(layout, action.path)
+
+  if action.kind == "resize" then
+    target.fraction = action.fraction
+  elseif action.kind == "select" then
+    target.selected = action.key
+  elseif action.kind == "close" then
+    take_tab(layout, action.key)
+  elseif action.kind == "drop" and (action.zone or "center") == "center" then
+    local index = action.index or #target.tabs + 1
+    local from = index_of(target.tabs, action.key)
+    if from then
+      table.remove(target.tabs, from)
+      if index > from then
+        index = index - 1
+      end
+    end
+    table.insert(target.tabs, math.min(index, #target.tabs + 1), action.key)
+    target.selected = action.key
+    take_tab(layout, action.key, target)
+  elseif action.kind == "drop" then
+    if #target.tabs == 1 and target.tabs[1] == action.key then
+      return nil
+    end
+    local leaf = { tabs = { action.key }, selected = action.key }
+    local rest = { tabs = target.tabs, selected = target.selected }
+    target.tabs, target.selected = nil, nil
+    target.split = (action.zone == "left" or action.zone == "right") and "horizontal" or "vertical"
+    target.fraction = 0.5
+    if action.zone == "left" or action.zone == "top" then
+      target.children = { leaf, rest }
+    else
+      target.children = { rest, leaf }
+    end
+    take_tab(layout, action.key, leaf)
+  end
+  return prune_layout(layout) or { tabs = {} }
+end
+
+local function render_dock(self, ui, node, path, nodes, titles, actions)
+  local id = (self.props.id or ("dock" .. self.id)) .. path
+  if node.split then
+    local fraction = node.fraction or 0.5
+    local resized, changed = ui:split(id, node.split == "vertical", fraction, function(ui)
+      render_dock(self, ui, node.children[1], path .. ".1", nodes, titles, actions)
+    end, function(ui)
+      render_dock(self, ui, node.children[2], path .. ".2", nodes, titles, actions)
+    end)
+    if changed then
+      table.insert(actions, { kind = "resize", path = path, fraction = resized })
+    end
+    return
+  end
+
+  local tabs = {}
+  for _, key in ipairs(node.tabs) do
+    table.insert(tabs, titles[key])
+  end
+  local selected = node.selected or node.tabs[1]
+  local result = ui:tab_bar(id, tabs, { selected = selected, draggable = true })
+  ui:separator()
+
+  if result.closed then
+    table.insert(actions, { kind = "close", path = path, key = result.closed })
+  elseif result.selected then
+    table.insert(actions, { kind = "select", path = path, key = result.selected })
+  end
+  if result.dropped then
+    table.insert(actions, { kind = "drop", path = path, key = result.dropped.key, index = result.dropped.index })
+  end
+
+  local dropped = ui:dock_target(id, function(ui)
+    if nodes[selected] then
+      render_from(Vec({ nodes[selected] }), ui)
+    end
+  end)
+  if dropped then
+    table.insert(actions, { kind = "drop", path = path, key = dropped.key, zone = dropped.zone })
+  end
+end
+
+-- Arranges its children, titled and keyed like those of `Tabs`, in groups
+-- of tabs laid out side by side or stacked. Dragging a tab onto another
+-- group's tab row moves it there, and dragging it onto the edge of a group
+-- splits that group. The arrangement is the `layout` prop, a plain table
+-- that can be saved with `@Persisted` or `ui.storage`:
+--
+--   { split = "horizontal", fraction = 0.25, children = {
+--     { tabs = { "files" }, selected = "files" },
+--     { tabs = { "editor", "preview" }, selected = "editor" },
+--   } }
+--
+-- Children missing from it are added to its first group. Changes to it are
+-- written back to `layout` when that is a State, and emitted as "layout".
+ui.Dock = register_element("dock", { closable = false }, function(self, ui)
+  local tabs, nodes = child_tabs(self, self.props.closable)
+  local titles = {}
+  for _, tab in ipairs(tabs) do
+    titles[tab.key] = tab
+  end
+
+  local layout = self:layout()
+  local fixed = fix_layout(layout, tabs, nodes)
+  if fixed then
+    self:set_layout(fixed)
+    layout = fixed
+  end
+
+  local actions = {}
+  render_dock(self, ui, layout, "", nodes, titles, actions)
+
+  for _, action in ipairs(actions) do
+    local changed = apply_dock_action(self:layout(), action)
+    if changed then
+      self:set_layout(changed)
+    end
+    if action.kind == "close" then
+      close_tab(self, action.key)
+    end
+  end
+end, function(Dock)
+
+  function Dock:layout()
+    local layout = self.props.layout
+    if type(layout) == "table" and (layout.split or layout.tabs) then
+      return layout
+    end
+    return self._layout or { tabs = {} }
+  end
+
+  function Dock:set_layout(layout)
+    if type(self.props.layout) == "table" then
+      self.props.layout = layout
+    else
+      self._layout = layout
+    end
+    emit_event(self, "layout", { value = layout })
+    return self
+  end
+end)
+
 function build_component(instance)
   return instance:build(instance.props or {})
 end
 
+local reconcile
+
+local function node_key(node)
+  return node.props and node.props.key
+end
+
+-- Matches the nodes of a freshly built list against the previous one, by
+-- `key` prop when they have one and by position otherwise, and puts the
+-- previous node back wherever it matched. Previous nodes left without a
+-- match are added to `dropped`.
+local function reconcile_items(old_items, new_items, dropped)
+  local keyed = {}
+  for i = 1, table.maxn(old_items) do
+    local node = old_items[i]
+    if instanceof(node, Node) and node_key(node) ~= nil then
+      keyed[node_key(node)] = node
+    end
+  end
+
+  for i = 1, table.maxn(new_items) do
+    local node = new_items[i]
+    local old
+    if instanceof(node, Node) and node_key(node) ~= nil then
+      old = keyed[node_key(node)]
+      keyed[node_key(node)] = nil
+    else
+      old = old_items[i]
+      if instanceof(old, Node) and node_key(old) ~= nil then
+        old = nil
+      end
+    end
+    new_items[i] = reconcile(old, node, dropped)
+  end
+
+  local reused = {}
+  for i = 1, table.maxn(new_items) do
+    if new_items[i] then
+      reused[new_items[i]] = true
+    end
+  end
+  for i = 1, table.maxn(old_items) do
+    local node = old_items[i]
+    if type(node) == "table" and not reused[node] then
+      table.insert(dropped, node)
+    end
+  end
+  return new_items
+end
+
+local function with_owner(node, fn, ...)
+  local owner, derived = current_owner, current_derived
+  current_owner, current_derived = node, nil
+  local result = fn(...)
+  current_owner, current_derived = owner, derived
+  return result
+end
+
+-- Builds `node`, disposing the derived states of its previous build once
+-- the new one has replaced it.
+local function build_owned(node)
+  local previous = node.__derived
+  node.__derived = {}
+  local built = with_owner(node, function()
+    current_derived = node.__derived
+    return build_component(node)
+  end)
+  for _, derived in ipairs(previous or {}) do
+    derived:unsubscribe()
+  end
+  return built
+end
+
+local function call_hook(node, name)
+  if node[name] then
+    with_owner(node, node[name], node)
+  end
+end
+
+-- Marks every node of a built tree, without going into the trees built by
+-- the components in it, which they own themselves.
+local function collect_nodes(node, found)
+  if instanceof(node, Vec) then
+    node:for_each(function(child) collect_nodes(child, found) end)
+  elseif instanceof(node, Node) then
+    found[node] = true
+    collect_nodes(node.children, found)
+  elseif type(node) == "table" and not node.__class then
+    for i = 1, table.maxn(node) do
+      collect_nodes(node[i], found)
+    end
+  end
+  return found
+end
+
+-- Runs the `unmounted` hooks of `node` and everything below it except the
+-- nodes in `kept`, then stops the subscriptions, tasks and timers they own.
+function unmount(node, kept)
+  kept = kept or {}
+  if not node or kept[node] then return end
+  if instanceof(node, Vec) then
+    node:for_each(function(child) unmount(child, kept) end)
+  elseif instanceof(node, Node) then
+    if node.__unmounted then return end
+    node.__unmounted = true
+    unmount(node.children, kept)
+    unmount(node.__previous, kept)
+    -- The rows a `VirtualList` keeps outside its children
+    for _, row in pairs(node._rows or {}) do
+      unmount(row.nodes, kept)
+    end
+    if node.__mounted then
+      call_hook(node, "unmounted")
+    end
+
+    for _, subscription in ipairs(node.__subscriptions or {}) do
+      subscription:unsubscribe()
+    end
+    for _, derived in ipairs(node.__derived or {}) do
+      derived:unsubscribe()
+    end
+    for _, task in ipairs(node.__tasks or {}) do
+      task.cancelled = true
+    end
+    for _, timer in ipairs(node.__timers or {}) do
+      ui.clear_timer(timer)
+    end
+    node.__subscriptions, node.__derived, node.__tasks, node.__timers = nil, nil, nil, nil
+  elseif type(node) == "table" and not node.__class then
+    for i = 1, table.maxn(node) do
+      unmount(node[i], kept)
+    end
+  end
+end
+
+-- Keeps `old` in place of `new` when both are the same kind of node, so it
+-- holds on to its states, pending focus requests and handlers added with
+-- `on`, and takes the props, children and `on_*` handlers of `new`. Matched
+-- components build again from the new props, and `new` goes to `dropped` so
+-- whatever its `prepare` started is stopped.
+function reconcile(old, new, dropped)
+  if not instanceof(new, Node) then
+    if type(new) == "table" and not new.__class and type(old) == "table" and not old.__class then
+      reconcile_items(old, new, dropped)
+    end
+    return new
+  end
+  if not instanceof(old, Node) or old == new or old.__class ~= new.__class then
+    return new
+  end
+  table.insert(dropped, new)
+
+  old.props = new.props
+  for k, v in pairs(new._dunders or {}) do
+    old._dunders[k] = v
+  end
+  if old._event_handlers and new._event_handlers then
+    old._event_handlers = old._event_handlers:filter(function(handler)
+      return not handler.from_props
+    end)
+    new._event_handlers:for_each(function(handler)
+      if handler.from_props then
+        old._event_handlers:push(handler)
+      end
+    end)
+  end
+
+  if old.build then
+    old.children = new.children
+    old.__built = nil
+  else
+    old.children = Vec(reconcile_items(old.children.items, new.children.items, dropped))
+  end
+  return old
+end
+
 function lml_create(name, props, ...)
   if default_elements[name] then
     props.children = {...}
@@ -781,7 +2324,7 @@ function StatedComponent(states)
       for k, v in pairs(states) do
         self[k] = State(v)
         table.insert(self[k]._on_set, function(val)
-          self:rebuild()
+          schedule(self, self.rebuild)
         end)
         table.insert(self.states, k)
       end
@@ -791,6 +2334,31 @@ function StatedComponent(states)
   end
 end
 
+-- Saves the listed states (all of `StatedComponent`'s when omitted) under
+-- `key` in `ui.storage`, restoring them when the component is created. Goes
+-- above `@StatedComponent` so its states exist by the time this runs.
+function Persisted(key, fields)
+  return function(_class)
+    function _class:init()
+      for _, name in ipairs(fields or self.states or {}) do
+        local state = self[name]
+        if instanceof(state, State) then
+          local storage_key = key .. "." .. name
+          local saved = ui.storage.get(storage_key)
+          if saved ~= nil then
+            state:set(saved)
+          end
+          table.insert(state._on_set, function(val)
+            ui.storage.set(storage_key, val)
+          end)
+        end
+      end
+    end
+
+    return _class
+  end
+end
+
 function ComponentValues(values)
   return function(_class)
     function _class:init()
@@ -828,7 +2396,7 @@ function Component(p)
         self.props = {}
       end
       if self.prepare and not self.__prepared then
-        self:prepare(self.props)
+        with_owner(self, self.prepare, self, self.props)
         self.__prepared = true
       else 
         self.__prepared = true
@@ -837,9 +2405,26 @@ function Component(p)
       
     function FuncComp:_render(ui)
       if self.build and not self.__built and self.__prepared then
-        self.__built = Vec({
-          build_component(self)
+        local built = Vec({
+          build_owned(self)
         })
+        local previous = self.__previous
+        if previous then
+          local dropped = {}
+          reconcile_items(previous.items, built.items, dropped)
+          unmount(dropped, collect_nodes(built, {}))
+        end
+        self.__built = built
+        self.__previous = built
+
+        render_from(self.__built, ui)
+        if previous then
+          call_hook(self, "updated")
+        else
+          self.__mounted = true
+          call_hook(self, "mounted")
+        end
+        return
       end
       render_from(self.__built, ui)
     end
@@ -847,8 +2432,482 @@ function Component(p)
   end
 end
 
+local ui_test = {
+  cases = Vec(),
+  index = 0,
+  passed = 0,
+  failed = 0,
+}
+
+ui.test = ui_test
+
+local function test_matches(node, query)
+  if type(query) == "number" then
+    return node.id == query
+  elseif type(query) == "string" then
+    local text = node.props.text
+    if type(text) == "table" then text = text.text end
+    return node.name == query or text == query
+  elseif type(query) == "table" then
+    if query.id ~= nil and node.id ~= query.id then return false end
+    if query.name ~= nil and node.name ~= query.name then return false end
+    if query.type ~= nil and not instanceof(node, query.type) then return false end
+    if query.text ~= nil then
+      local text = node.props.text
+      if type(text) == "table" then text = text.text end
+      if text ~= query.text then return false end
+    end
+    return true
+  end
+  return false
+end
+
+local function test_walk(node, fn)
+  if not node then return end
+  if instanceof(node, Vec) then
+    node:for_each(function(child) test_walk(child, fn) end)
+  elseif instanceof(node, Node) then
+    fn(node)
+    test_walk(node.children, fn)
+    test_walk(node.__built, fn)
+  elseif type(node) == "table" then
+    for _, child in ipairs(node) do
+      test_walk(child, fn)
+    end
+  end
+end
+
+local function rect_center(node)
+  if not node or not node.response then
+    error("widget has not been rendered yet", 3)
+  end
+  local r = node.response.rect
+  return (r[1] + r[3]) / 2, (r[2] + r[4]) / 2
+end
+
+class! TestContext, {
+  mount(comp, props){
+    unmount(self.root)
+    if instanceof(comp, Node) then
+      self.root = comp
+    else
+      self.root = comp(props or {})
+    end
+    ui_test.root = self.root
+    return self.root
+  }
+
+  frame(n){
+    for i = 1, (n or 1) do
+      coroutine.yield()
+      if ui_test.render_error then
+        local err = ui_test.render_error
+        ui_test.render_error = nil
+        error(err, 0)
+      end
+    end
+    return self
+  }
+
+  find_all(query){
+    local found = Vec()
+    local function match(node)
+      if test_matches(node, query) then
+        found:push(node)
+      end
+    end
+    test_walk(self.root, match)
+    test_walk(dialogs, match)
+    return found
+  }
+
+  find(query){
+    return self:find_all(query):get(1)
+  }
+
+  get(query){
+    local node = self:find(query)
+    if not node then
+      error("no widget matches " .. tostring(type(query) == "table" and (query.text or query.name or query.id) or query), 2)
+    end
+    return node
+  }
+
+  resolve(target){
+    if instanceof(target, Node) then
+      return target
+    end
+    return self:get(target)
+  }
+
+  hover(target){
+    local x, y = rect_center(self:resolve(target))
+    push_input_event({ type = "pointer_moved", x = x, y = y })
+    return self:frame()
+  }
+
+  click(target, button){
+    local x, y = rect_center(self:resolve(target))
+    self:hover(target)
+    push_input_event({ type = "pointer_button", x = x, y = y, button = button, pressed = true })
+    self:frame()
+    push_input_event({ type = "pointer_button", x = x, y = y, button = button, pressed = false })
+    return self:frame()
+  }
+
+  type_text(text){
+    push_input_event({ type = "text", text = text })
+    return self:frame()
+  }
+
+  key(name, modifiers){
+    push_input_event({ type = "key", key = name, pressed = true, modifiers = modifiers })
+    self:frame()
+    push_input_event({ type = "key", key = name, pressed = false, modifiers = modifiers })
+    return self:frame()
+  }
+
+  assert(cond, message){
+    if not cond then
+      error(message or "assertion failed", 2)
+    end
+    return self
+  }
+
+  assert_eq(a, b, message){
+    if a ~= b then
+      error((message and message .. ": " or "") .. "expected " .. tostring(b) .. ", got " .. tostring(a), 2)
+    end
+    return self
+  }
+}
+
+function ui.test.case(name, fn)
+  ui_test.cases:push({ name = name, fn = fn })
+end
+
+function ui.test.step()
+  if not ui_test.current then
+    ui_test.index = ui_test.index + 1
+    local case = ui_test.cases:get(ui_test.index)
+    if not case then
+      print(ui_test.passed .. " passed, " .. ui_test.failed .. " failed")
+      request_exit(ui_test.failed > 0 and 1 or 0)
+      return
+    end
+    local context = TestContext()
+    ui_test.current = {
+      name = case.name,
+      co = coroutine.create(function() case.fn(context) end)
+    }
+  end
+
+  local current = ui_test.current
+  local ok, err = coroutine.resume(current.co)
+
+  if not ok then
+    print("FAILED " .. current.name .. ": " .. tostring(err))
+    ui_test.failed = ui_test.failed + 1
+  elseif coroutine.status(current.co) == "dead" then
+    print("ok " .. current.name)
+    ui_test.passed = ui_test.passed + 1
+  else
+    return
+  end
+
+  unmount(ui_test.root)
+  unmount(Vec(dialogs))
+  dialogs = {}
+  ui_test.current = nil
+  ui_test.root = nil
+  ui_test.render_error = nil
+end
+
+local function walk_states(node, path, fn)
+  if not node then return end
+  if instanceof(node, Vec) then
+    local index = 0
+    node:for_each(function(child)
+      index = index + 1
+      walk_states(child, path .. "/" .. index, fn)
+    end)
+  elseif instanceof(node, Node) then
+    path = path .. ":" .. tostring(node.name or "_")
+    for key, value in pairs(node) do
+      if type(key) == "string" and key:sub(1, 1) ~= "_" and instanceof(value, State)
+        and not instanceof(value, Computed) and not instanceof(value, Resource) then
+        fn(path .. "." .. key, value)
+      end
+    end
+    walk_states(node.children, path, fn)
+    walk_states(node.__built, path, fn)
+  elseif type(node) == "table" then
+    for index, child in ipairs(node) do
+      walk_states(child, path .. "/" .. index, fn)
+    end
+  end
+end
+
+local pending_states
+
+-- The values of every `State` owned by a mounted component, keyed by the
+-- component's place in the tree.
+function ui.snapshot_state()
+  local values = {}
+  walk_states(elements, "", function(key, state)
+    values[key] = state:get()
+  end)
+  return values
+end
+
+-- Puts back values taken with `snapshot_state` into the components that
+-- still exist. Children built from restored state get theirs on the
+-- following frames.
+function ui.restore_state(values)
+  pending_states = values
+end
+
+local function restore_pending_states()
+  local restored = false
+  walk_states(elements, "", function(key, state)
+    if pending_states[key] ~= nil then
+      state:set(pending_states[key])
+      pending_states[key] = nil
+      restored = true
+    end
+  end)
+  if not restored then
+    pending_states = nil
+  end
+end
+
+local panel_names = { side_panel = true, top_bottom_panel = true, central_panel = true }
+-- Elements that render their children in place, so panels can sit in them.
+local transparent_names = { style = true, provider = true, error_boundary = true, suspense = true }
+
+local function has_panels(node)
+  if instanceof(node, Vec) then
+    local found = false
+    node:for_each(function(child)
+      found = found or has_panels(child)
+    end)
+    return found
+  elseif instanceof(node, Node) then
+    if panel_names[node.name] then
+      return true
+    elseif node.build then
+      return has_panels(node.__built or node.__previous)
+    elseif transparent_names[node.name] then
+      return has_panels(node.children)
+    end
+  end
+  return false
+end
+
+local function root_nodes()
+  return ui_test.root and Vec({ ui_test.root }) or elements
+end
+
+local shell_panels = false
+
+-- Whether the root of the tree is laid out in panels, so the app shell
+-- should not put it in a central panel of its own.
+function ui.declares_panels()
+  shell_panels = has_panels(root_nodes())
+  return shell_panels
+end
+
 local function render_ui(ui)
-  render_from(elements, ui)
+  current_owner, current_derived, current_reads = nil, nil, nil
+  context_stack = {}
+  suspense_stack = {}
+  if batch_depth > 0 then
+    -- Left open by an error in a batch.
+    flush_batch()
+  end
+  if pending_states then
+    restore_pending_states()
+  end
+
+  if ui_test.root then
+    local ok, err = pcall(function()
+      render_from(Vec({ ui_test.root }), ui)
+      render_dialogs(ui)
+    end)
+    if not ok then
+      ui_test.render_error = err
+    end
+  else
+    render_from(elements, ui)
+    render_dialogs(ui)
+  end
+
+  -- The root only shows whether it has panels once it is built.
+  if has_panels(root_nodes()) ~= shell_panels then
+    repaint()
+  end
+end
+
+function ui.exit(code)
+  request_exit(code or 0)
+end
+
+-- Values kept across restarts of the app, saved on exit and periodically.
+-- Anything serializable to JSON can be stored.
+ui.storage = {
+  get = function(key, default)
+    local value = storage_get(key)
+    if value == nil then return default end
+    return value
+  end,
+  set = function(key, value) storage_set(key, value) end,
+  remove = function(key) storage_set(key, nil) end,
+  keys = function() return storage_keys() end,
+}
+
+-- Changes the native window at runtime, e.g. `ui.window.set_size(800, 600)`
+-- or `ui.window.set_fullscreen(true)`. Icon paths are relative to the
+-- directory of lulu.conf.lua, as in `manifest.window`.
+ui.window = {
+  focus = function() send_viewport_command("focus") end,
+  close = function() send_viewport_command("close") end,
+}
+
+for _, name in ipairs({
+  "title", "size", "min_size", "max_size", "position", "resizable", "decorations",
+  "transparent", "visible", "always_on_top", "fullscreen", "maximized", "minimized", "icon"
+}) do
+  ui.window["set_" .. name] = function(value, y)
+    if y ~= nil then value = { value, y } end
+    send_viewport_command(name, value)
+  end
+end
+
+-- Asks for another frame, right away or after `after` seconds. Changing a
+-- `State` already does this.
+function ui.request_repaint(after)
+  request_repaint(after)
+end
+
+local timers = {}
+local timer_id = 0
+local timer_now = 0
+
+local function add_timer(fn, seconds, every)
+  timer_id = timer_id + 1
+  timers[timer_id] = { fn = fn, at = timer_now + seconds, every = every }
+  own("__timers", timer_id)
+  repaint(seconds)
+  return timer_id
+end
+
+-- Calls `fn` once after `seconds`. Returns an id for `ui.clear_timer`.
+function ui.set_timeout(fn, seconds)
+  return add_timer(fn, seconds or 0)
+end
+
+-- Calls `fn` every `seconds` until cleared with `ui.clear_timer`.
+function ui.set_interval(fn, seconds)
+  return add_timer(fn, seconds or 0, seconds or 0)
+end
+
+function ui.clear_timer(id)
+  if id then
+    timers[id] = nil
+  end
+end
+
+-- Runs the timers due at `now` (egui's input time), called each frame.
+-- Returns the seconds until the next deadline, if any timer is left.
+function ui.run_timers(now)
+  timer_now = now
+
+  local due = {}
+  for id, timer in pairs(timers) do
+    if timer.at <= now then
+      table.insert(due, id)
+    end
+  end
+  table.sort(due)
+
+  for _, id in ipairs(due) do
+    -- An earlier callback may have cleared it
+    local timer = timers[id]
+    if timer then
+      if timer.every then
+        timer.at = math.max(timer.at + timer.every, now)
+      else
+        timers[id] = nil
+      end
+      local ok, err = xpcall(timer.fn, debug.traceback)
+      if not ok then
+        ui.report_error("timer", err)
+      end
+    end
+  end
+
+  local next_at
+  for _, timer in pairs(timers) do
+    if not next_at or timer.at < next_at then
+      next_at = timer.at
+    end
+  end
+  return next_at and math.max(next_at - now, 0)
+end
+
+-- Tasks started by a component are cancelled when it unmounts. The original
+-- constructor is kept aside so reloading this file does not wrap it twice.
+local new_future = Future.__new or Future.new
+Future.__new = new_future
+function Future.new(fn)
+  local future = new_future(fn)
+  own("__tasks", future)
+  return future
+end
+
+-- Polls every pending `async` task once, called each frame. lulu's own
+-- `Future.scheduler` stops for good once its queue is empty, so tasks
+-- started later would never run. Returns whether a task yielded without
+-- waiting on a Rust future and so needs another frame to make progress.
+function ui.poll_tasks()
+  local busy = false
+  local i = 1
+  while i <= #__future_stack do
+    local fut = __future_stack[i]
+    if not fut.done and not fut.cancelled then
+      local ok, res = coroutine.resume(fut.co)
+      if not ok then
+        fut.error = res
+        fut.done = true
+      elseif coroutine.status(fut.co) == "dead" then
+        fut.result = res
+        fut.done = true
+      elseif res ~= ASYNC_POLL_PENDING then
+        busy = true
+      end
+    end
+
+    if fut.cancelled then
+      table.remove(__future_stack, i)
+    elseif fut.done then
+      table.remove(__future_stack, i)
+      local ok, err = xpcall(function() fut:last() end, debug.traceback)
+      if not ok then
+        ui.report_error("task", err)
+      end
+    else
+      i = i + 1
+    end
+  end
+  return busy
+end
+
+function ui.report_error(kind, err)
+  if ui_test.root then
+    ui_test.render_error = ui_test.render_error or err
+  else
+    report_error(kind, tostring(err))
+  end
 end
 
 function RenderComponent(comp)
@@ -3,6 +3,11 @@
 use mlua::prelude::LuaError;
 mod ui;
 mod shape;
+mod headless;
+mod dev;
+mod sources;
+mod storage;
+mod window;
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 4)]
 async fn main() -> mlua::Result<()> {
@@ -16,19 +21,33 @@ async fn main() -> mlua::Result<()> {
 
   lulu.compiler.compile(include_str!("./lua/macros.lua"), None, None);
 
+  let options = headless::HeadlessOptions::from_args(&lulu.args);
+  let test_entry = options.as_ref().and_then(|options| options.entry.clone());
+
+  let mut entry = None;
   if let Some(mods) = lulu::bundle::load_embedded_scripts() {
     lulu::bundle::reg_bundle_nods(&mut lulu, mods)?;
+  } else if let Some(path) = &test_entry {
+    let name = lulu.entry_mod_path(path.clone())?;
+    lulu.lua.set_app_data(ui::MainModule(name));
+    entry = Some(path.as_path());
   } else {
     let path = std::path::Path::new("main.lua");
     if path.exists() {
       lulu.entry_mod_path(path.to_path_buf())?;
+      entry = Some(path);
     }
     let path = std::path::Path::new("test/main.lua");
     if path.exists() {
       lulu.entry_mod_path(path.to_path_buf())?;
+      entry = Some(path);
     }
   }
-  lulu::handle_error!(ui::run(&mut lulu).await.map_err(|e| mlua::Error::external(e.to_string())));
+  if let Some(options) = options {
+    std::process::exit(headless::run(&mut lulu, entry, options).await);
+  }
+
+  lulu::handle_error!(ui::run(&mut lulu, entry).await.map_err(|e| mlua::Error::external(e.to_string())));
 
   Ok(())
 }
@@ -22,10 +22,10 @@ pub fn from_lua_table(table: mlua::Table) -> Option<Shape> {
         let rect = Rect::from_min_size(pos2(x, y), vec2(w, h));
 
         let fill: mlua::Table = table.get("fill").ok()?;
-        let fill_color = crate::ui::color_from_lua_table(fill).unwrap();
+        let fill_color = crate::ui::color_from_lua_table(fill)?;
 
         let stroke: mlua::Table = table.get("stroke").ok()?;
-        let stroke_color = crate::ui::color_from_lua_table(stroke.clone()).unwrap();
+        let stroke_color = crate::ui::color_from_lua_table(stroke.clone())?;
         let stroke_width: f32 = stroke.get("width").ok()?;
 
         Some(Shape::Rect(RectShape::new(
@@ -42,10 +42,10 @@ pub fn from_lua_table(table: mlua::Table) -> Option<Shape> {
         let center = pos2(x, y);
 
         let fill: mlua::Table = table.get("fill").ok()?;
-        let fill_color = crate::ui::color_from_lua_table(fill).unwrap();
+        let fill_color = crate::ui::color_from_lua_table(fill)?;
 
         let stroke: mlua::Table = table.get("stroke").ok()?;
-        let stroke_color = crate::ui::color_from_lua_table(stroke.clone()).unwrap();
+        let stroke_color = crate::ui::color_from_lua_table(stroke.clone())?;
         let stroke_width: f32 = stroke.get("width").ok()?;
 
         Some(Shape::Circle(CircleShape{
@@ -63,7 +63,7 @@ pub fn from_lua_table(table: mlua::Table) -> Option<Shape> {
         let points = [pos2(x1, y1), pos2(x2, y2)];
 
         let color: mlua::Table = table.get("color").ok()?;
-        let color = crate::ui::color_from_lua_table(color).unwrap();
+        let color = crate::ui::color_from_lua_table(color)?;
         let width: f32 = table.get("width").ok()?;
 
         Some(Shape::LineSegment {
@@ -0,0 +1,272 @@
+use lulu::conf::find_lulu_conf;
+use lulu::lulu::{Lulu, LuluModSource};
+use lulu::sourcemap::generate_sourcemap;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// A module of the running app that was compiled from a file on disk.
+#[derive(Clone)]
+pub struct SourceFile {
+  pub name: String,
+  pub path: PathBuf,
+}
+
+/// Where the modules of the running app were loaded from, used to point Lua
+/// errors back at the original `.lua` files.
+#[derive(Default)]
+pub struct Sources {
+  entry: Option<PathBuf>,
+  files: Vec<SourceFile>,
+  line_maps: RefCell<HashMap<String, Vec<usize>>>,
+}
+
+impl Sources {
+  pub fn collect(lulu: &Lulu, entry: Option<&Path>) -> Self {
+    let mut files: Vec<SourceFile> = Vec::new();
+
+    if let Some(entry) = entry {
+      let entry_name = entry.to_string_lossy().to_string();
+      if lulu.mods.iter().any(|m| m.name == entry_name) {
+        files.push(SourceFile {
+          name: entry_name,
+          path: entry.to_path_buf(),
+        });
+      }
+
+      if let Some(conf_path) = find_lulu_conf(entry.to_path_buf())
+        && let Some(root) = conf_path.parent()
+      {
+        for lmod in &lulu.mods {
+          let Some(conf) = &lmod.conf else { continue };
+          let Some(mods) = &conf.mods else { continue };
+
+          let prefix = conf
+            .manifest
+            .as_ref()
+            .and_then(|m| m.get::<String>("name").ok())
+            .map(|n| format!("{}/", n))
+            .unwrap_or_default();
+
+          for (name, path) in mods {
+            if lmod.name == format!("{}{}", prefix, name) {
+              files.push(SourceFile {
+                name: lmod.name.clone(),
+                path: root.join(path),
+              });
+            }
+          }
+        }
+      }
+    }
+
+    for (name, (path_to_import, path_from, _)) in &lulu.compiler.importmap {
+      if let Some(from) = path_from
+        && let Some(parent) = Path::new(from).parent()
+      {
+        files.push(SourceFile {
+          name: name.clone(),
+          path: parent.join(path_to_import),
+        });
+      }
+    }
+
+    Self {
+      entry: entry.map(Path::to_path_buf),
+      files,
+      line_maps: RefCell::new(HashMap::new()),
+    }
+  }
+
+  /// Collects the files again after the modules were recompiled, which may
+  /// have added imports and changed every line mapping.
+  pub fn refresh(&mut self, lulu: &Lulu) {
+    *self = Self::collect(lulu, self.entry.as_deref());
+  }
+
+  pub fn files(&self) -> &[SourceFile] {
+    &self.files
+  }
+
+  /// Maps a line of the compiled chunk `name` to `path:line` in its source.
+  pub fn locate(&self, lulu: &Lulu, name: &str, line: usize) -> Option<String> {
+    let file = self.files.iter().find(|f| f.name == name)?;
+
+    let mut line_maps = self.line_maps.borrow_mut();
+    if !line_maps.contains_key(name) {
+      let original = std::fs::read_to_string(&file.path).ok()?;
+      let compiled = match &lulu.mods.iter().find(|m| m.name == name)?.source {
+        LuluModSource::Code(code) => code.clone(),
+        LuluModSource::Bytecode(_) => return None,
+      };
+      line_maps.insert(name.to_string(), line_map(&original, &compiled));
+    }
+
+    let map = &line_maps[name];
+    let original = map.get(line.checked_sub(1)?).copied()?;
+    Some(format!("{}:{}", file.path.display(), original + 1))
+  }
+
+  /// Rewrites every `[string "name"]:line` chunk reference in `text` to the
+  /// source location it was compiled from, when that is known.
+  pub fn rewrite(&self, lulu: &Lulu, text: &str) -> String {
+    const OPEN: &str = "[string \"";
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find(OPEN) {
+      out.push_str(&rest[..start]);
+      let after = &rest[start + OPEN.len()..];
+
+      let Some(end) = after.find("\"]:") else {
+        out.push_str(&rest[start..]);
+        return out;
+      };
+      let name = &after[..end];
+      let digits = &after[end + 3..];
+      let len = digits.chars().take_while(|c| c.is_ascii_digit()).count();
+
+      match digits[..len]
+        .parse::<usize>()
+        .ok()
+        .and_then(|line| self.locate(lulu, name, line))
+      {
+        Some(location) => {
+          out.push_str(&location);
+          rest = &digits[len..];
+        }
+        None => {
+          out.push_str(OPEN);
+          rest = after;
+        }
+      }
+    }
+
+    out.push_str(rest);
+    out
+  }
+}
+
+/// The most cells a matching table may have. `generate_sourcemap` needs one
+/// per pair of characters, hundreds of megabytes for a file of a few hundred
+/// lines, so past this it is skipped for matching lines, which needs one per
+/// pair of lines and gives up past it too.
+const SOURCEMAP_CELLS: usize = 16_000_000;
+
+/// For each line of `compiled`, the index of the line in `original` it came
+/// from, taken from lulu's character-level source map. Lines with nothing
+/// mapped take the line above them.
+fn line_map(original: &str, compiled: &str) -> Vec<usize> {
+  let lines = compiled.lines().count();
+  if original.len().saturating_mul(compiled.len()) > SOURCEMAP_CELLS {
+    return match_lines(original, compiled);
+  }
+
+  let mut map = vec![None; lines];
+  for entry in generate_sourcemap(original, compiled) {
+    if let Some(line) = map.get_mut(entry.lua_line)
+      && line.is_none()
+    {
+      *line = Some(entry.lulu_line);
+    }
+  }
+
+  let mut last = 0;
+  map
+    .into_iter()
+    .map(|m| {
+      if let Some(m) = m {
+        last = m;
+      }
+      last
+    })
+    .collect()
+}
+
+/// Like `line_map` for chunks too large for `generate_sourcemap`: lines are
+/// matched with an LCS over their trimmed text and unmatched lines take the
+/// closest match above them.
+fn match_lines(original: &str, compiled: &str) -> Vec<usize> {
+  let a: Vec<&str> = original.lines().map(str::trim).collect();
+  let b: Vec<&str> = compiled.lines().map(str::trim).collect();
+
+  if a.len().saturating_mul(b.len()) > SOURCEMAP_CELLS {
+    return (0..b.len())
+      .map(|i| i.min(a.len().saturating_sub(1)))
+      .collect();
+  }
+
+  let width = b.len() + 1;
+  let mut dp = vec![0u32; (a.len() + 1) * width];
+  for i in (0..a.len()).rev() {
+    for j in (0..b.len()).rev() {
+      dp[i * width + j] = if a[i] == b[j] {
+        dp[(i + 1) * width + j + 1] + 1
+      } else {
+        dp[(i + 1) * width + j].max(dp[i * width + j + 1])
+      };
+    }
+  }
+
+  let mut map = vec![None; b.len()];
+  let (mut i, mut j) = (0, 0);
+  while i < a.len() && j < b.len() {
+    if a[i] == b[j] {
+      map[j] = Some(i);
+      i += 1;
+      j += 1;
+    } else if dp[(i + 1) * width + j] >= dp[i * width + j + 1] {
+      i += 1;
+    } else {
+      j += 1;
+    }
+  }
+
+  let mut last = 0;
+  map
+    .into_iter()
+    .map(|m| {
+      if let Some(m) = m {
+        last = m;
+      }
+      last
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn maps_lines_after_a_multi_line_macro_back_to_the_source() {
+    let original = "local a = 1
+
+macro {
+  twice($e) {
+    $e
+    $e
+  }
+}
+
+twice! { print(a) }
+local b = a + 1
+error(\"boom\")
+";
+    let compiled = lulu::compiler::Compiler::new(None).compile(original, None, None);
+    let map = line_map(original, &compiled);
+
+    let error_line = compiled
+      .lines()
+      .position(|l| l.starts_with("error("))
+      .unwrap();
+    assert_eq!(map[error_line], 11);
+    let b_line = compiled
+      .lines()
+      .position(|l| l.starts_with("local b"))
+      .unwrap();
+    assert_eq!(map[b_line], 10);
+    assert_eq!(map[0], 0);
+  }
+}
@@ -0,0 +1,77 @@
+use lulu::serde_json;
+use mlua::LuaSerdeExt;
+
+/// Key under which the `ui.storage` values are kept in eframe's storage.
+const STORAGE_KEY: &str = "lulu_storage";
+
+/// The key/value store behind `ui.storage`, kept as Lua app data so it
+/// survives hot reloads of `ui.lua`.
+#[derive(Default)]
+struct AppStorage(serde_json::Map<String, serde_json::Value>);
+
+/// Loads the values saved by a previous run, if any, and registers the
+/// `storage_get`, `storage_set` and `storage_keys` functions `ui.storage` is
+/// built on. Must run before the main module, which may read stored values
+/// while its components are created.
+pub fn install(lua: &mlua::Lua, storage: Option<&dyn eframe::Storage>) -> mlua::Result<()> {
+  let values = storage
+    .and_then(|storage| storage.get_string(STORAGE_KEY))
+    .and_then(|saved| serde_json::from_str(&saved).ok())
+    .unwrap_or_default();
+  lua.set_app_data(AppStorage(values));
+
+  lua.globals().set(
+    "storage_get",
+    lua.create_function(|lua, key: String| {
+      let value = lua
+        .app_data_ref::<AppStorage>()
+        .and_then(|storage| storage.0.get(&key).cloned());
+      match value {
+        Some(value) => lua.to_value(&value),
+        None => Ok(mlua::Value::Nil),
+      }
+    })?,
+  )?;
+
+  lua.globals().set(
+    "storage_set",
+    lua.create_function(|lua, (key, value): (String, mlua::Value)| {
+      let value: serde_json::Value = match value {
+        mlua::Value::Nil => serde_json::Value::Null,
+        value => lua
+          .from_value(value)
+          .map_err(|err| mlua::Error::runtime(format!("ui.storage.set: '{}': {}", key, err)))?,
+      };
+      if let Some(mut storage) = lua.app_data_mut::<AppStorage>() {
+        if value.is_null() {
+          storage.0.remove(&key);
+        } else {
+          storage.0.insert(key, value);
+        }
+      }
+      Ok(())
+    })?,
+  )?;
+
+  lua.globals().set(
+    "storage_keys",
+    lua.create_function(|lua, ()| {
+      Ok(
+        lua
+          .app_data_ref::<AppStorage>()
+          .map(|storage| storage.0.keys().cloned().collect::<Vec<_>>())
+          .unwrap_or_default(),
+      )
+    })?,
+  )
+}
+
+/// Writes the `ui.storage` values into eframe's storage, which eframe does
+/// on exit and every `auto_save_interval`.
+pub fn save(lua: &mlua::Lua, storage: &mut dyn eframe::Storage) {
+  if let Some(values) = lua.app_data_ref::<AppStorage>()
+    && let Ok(saved) = serde_json::to_string(&values.0)
+  {
+    storage.set_string(STORAGE_KEY, saved);
+  }
+}
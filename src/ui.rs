@@ -1,8 +1,13 @@
+use crate::dev::{self, DevReload};
 use crate::shape::{self, LuaShape, from_lua_table};
+use crate::sources::Sources;
+use crate::storage;
+use crate::window;
 use eframe::egui::*;
 use eframe::egui::{self, Align2, FontId, ahash::HashMap};
 use lulu::lulu::{Lulu, LuluModSource};
-use mlua::{LuaSerdeExt, UserData, UserDataMethods};
+use std::path::Path;
+use mlua::{ErrorContext, LuaSerdeExt, UserData, UserDataMethods};
 
 pub fn color_from_lua_table(table: mlua::Table) -> Option<Color32> {
   let r: u8 = table.get(1).ok()?;
@@ -12,6 +17,61 @@ pub fn color_from_lua_table(table: mlua::Table) -> Option<Color32> {
   Some(Color32::from_rgba_unmultiplied(r, g, b, (a * 255.0) as u8))
 }
 
+fn lua_color(table: mlua::Table) -> mlua::Result<Color32> {
+  color_from_lua_table(table)
+    .ok_or_else(|| mlua::Error::runtime("expected a color table { r, g, b [, a] }"))
+}
+
+fn lua_key(name: &str) -> mlua::Result<egui::Key> {
+  egui::Key::from_name(name)
+    .ok_or_else(|| mlua::Error::runtime(format!("unknown key name '{}'", name)))
+}
+
+/// Parses a shortcut such as `Ctrl+Shift+S`. `Ctrl` and `Cmd` both stand
+/// for the platform's command key.
+fn lua_shortcut(text: &str) -> mlua::Result<egui::KeyboardShortcut> {
+  let parts: Vec<&str> = text.split('+').map(str::trim).collect();
+  let (key, names) = parts.split_last().expect("split yields at least one part");
+  let mut modifiers = egui::Modifiers::NONE;
+  for name in names {
+    modifiers = modifiers
+      | match name.to_ascii_lowercase().as_str() {
+        "ctrl" | "cmd" | "command" => egui::Modifiers::COMMAND,
+        "shift" => egui::Modifiers::SHIFT,
+        "alt" | "option" => egui::Modifiers::ALT,
+        _ => {
+          return Err(mlua::Error::runtime(format!(
+            "unknown modifier '{}' in shortcut '{}'",
+            name, text
+          )));
+        }
+      };
+  }
+  Ok(egui::KeyboardShortcut::new(modifiers, lua_key(key)?))
+}
+
+/// Context for an error raised while reading argument `arg` of `ui:widget`.
+fn bad_arg(widget: &str, arg: &str) -> String {
+  format!("ui:{}: invalid `{}`", widget, arg)
+}
+
+/// Context for an error raised while reading field `name` of a style table.
+/// Contexts from nested tables are folded into one dotted path, so a bad
+/// `visuals.hovered.bg_stroke` reports the whole path.
+fn field_context(name: &str, err: &mlua::Error) -> String {
+  match err {
+    mlua::Error::WithContext { context, .. } => {
+      context.replacen("invalid `", &format!("invalid `{}.", name), 1)
+    }
+    _ => format!("invalid `{}`", name),
+  }
+}
+
+/// Context for an error raised inside the Lua callback of `ui:widget`.
+fn in_callback(widget: &str) -> String {
+  format!("in ui:{} callback", widget)
+}
+
 fn to_align(s: &str) -> egui::Align {
   match s {
     "start" => egui::Align::Min,
@@ -45,6 +105,16 @@ macro_rules! set_attrib {
       $setter(val);
     }
   };
+  (try ($name:expr, $type:ty), $table:expr, $setter:expr) => {
+    if let Ok(val) = $table.get::<$type>($name) {
+      $setter(val).with_context(|err| field_context($name, err))?;
+    }
+  };
+  (try ($name:expr, $type:ty) in $widget:expr, $table:expr, $setter:expr) => {
+    if let Ok(val) = $table.get::<$type>($name) {
+      $setter(val).with_context(|err| format!("ui:{}: {}", $widget, field_context($name, err)))?;
+    }
+  };
 }
 
 macro_rules! is_color {
@@ -64,47 +134,53 @@ macro_rules! ui_resp {
   };
 }
 
-macro_rules! table_into_margin {
-  ($val:expr) => {
-    match $val {
-      mlua::Value::Table(t) => {
-        if t.len().unwrap() > 2 {
-          Margin::symmetric(t.get(1).unwrap(), t.get(2).unwrap())
-        } else {
-          Margin {
-            top: t.get(1).unwrap(),
-            left: t.get(2).unwrap(),
-            right: t.get(3).unwrap(),
-            bottom: t.get(4).unwrap(),
-          }
+fn table_into_margin(val: mlua::Value) -> mlua::Result<Margin> {
+  Ok(match val {
+    mlua::Value::Table(t) => {
+      if t.raw_len() <= 2 {
+        Margin::symmetric(t.get(1)?, t.get(2)?)
+      } else {
+        Margin {
+          top: t.get(1)?,
+          left: t.get(2)?,
+          right: t.get(3)?,
+          bottom: t.get(4)?,
         }
       }
-      mlua::Value::Number(n) => Margin::same(n as f32),
-      _ => Margin::same(1.0),
     }
-  };
+    mlua::Value::Number(n) => Margin::same(n as f32),
+    mlua::Value::Integer(n) => Margin::same(n as f32),
+    _ => Margin::same(1.0),
+  })
 }
-macro_rules! table_into_rounding {
-  ($val:expr) => {
-    match $val {
-      mlua::Value::Table(t) => Rounding {
-        ne: t.get(1).unwrap(),
-        nw: t.get(2).unwrap(),
-        se: t.get(3).unwrap(),
-        sw: t.get(4).unwrap(),
-      },
-      mlua::Value::Number(n) => Rounding::same(n as f32),
-      _ => Rounding::same(1.0),
-    }
-  };
+
+fn table_into_rounding(val: mlua::Value) -> mlua::Result<Rounding> {
+  Ok(match val {
+    mlua::Value::Table(t) => Rounding {
+      ne: t.get(1)?,
+      nw: t.get(2)?,
+      se: t.get(3)?,
+      sw: t.get(4)?,
+    },
+    mlua::Value::Number(n) => Rounding::same(n as f32),
+    mlua::Value::Integer(n) => Rounding::same(n as f32),
+    _ => Rounding::same(1.0),
+  })
+}
+
+fn table_into_stroke(val: mlua::Table) -> mlua::Result<Stroke> {
+  Ok(Stroke {
+    width: val.get::<f32>("width")?,
+    color: lua_color(val.get::<mlua::Table>("color")?)?,
+  })
 }
 
 macro_rules! widget_style {
   ($state:ident, $table:expr, $style:expr) => {
     set_attrib!(
-      (stringify!($state), mlua::Table),
+      try (stringify!($state), mlua::Table),
       $table,
-      |style_table: mlua::Table| {
+      |style_table: mlua::Table| -> mlua::Result<()> {
         set_attrib!(("bg_fill", mlua::Table), style_table, |val: mlua::Table| {
           is_color!(val, $style.visuals.widgets.$state.bg_fill);
         });
@@ -116,78 +192,73 @@ macro_rules! widget_style {
           }
         );
         set_attrib!(
-          ("bg_stroke", mlua::Table),
+          try ("bg_stroke", mlua::Table),
           style_table,
-          |val: mlua::Table| {
-            let color = color_from_lua_table(val.get::<mlua::Table>("color").unwrap()).unwrap();
-            let width = val.get::<f32>("width").unwrap();
-            $style.visuals.widgets.$state.bg_stroke = Stroke { width, color }
+          |val: mlua::Table| -> mlua::Result<()> {
+            $style.visuals.widgets.$state.bg_stroke = table_into_stroke(val)?;
+            Ok(())
           }
         );
         set_attrib!(
-          ("fg_stroke", mlua::Table),
+          try ("fg_stroke", mlua::Table),
           style_table,
-          |val: mlua::Table| {
-            let color = color_from_lua_table(val.get::<mlua::Table>("color").unwrap()).unwrap();
-            let width = val.get::<f32>("width").unwrap();
-            $style.visuals.widgets.$state.fg_stroke = Stroke { width, color }
+          |val: mlua::Table| -> mlua::Result<()> {
+            $style.visuals.widgets.$state.fg_stroke = table_into_stroke(val)?;
+            Ok(())
           }
         );
         set_attrib!(
-          ("rounding", mlua::Value),
+          try ("rounding", mlua::Value),
           style_table,
-          |val: mlua::Value| {
-            $style.visuals.widgets.$state.rounding = table_into_rounding!(val);
+          |val: mlua::Value| -> mlua::Result<()> {
+            $style.visuals.widgets.$state.rounding = table_into_rounding(val)?;
+            Ok(())
           }
         );
         set_attrib!(("expansion", f32), style_table, |val: f32| {
           $style.visuals.widgets.$state.expansion = val;
         });
+        Ok(())
       }
     );
   };
 }
 
 macro_rules! scoped_function_call {
-  ($lua:expr, $ui:expr,  $func:expr) => {
-    $lua
-      .scope(|scope| {
-        let lua_ui = scope.create_userdata(LuaUi { ui: $ui }).unwrap();
-        let temp_func = scope
-          .create_function(move |_lua, ()| $func.call::<()>(lua_ui.clone()))
-          .unwrap();
-        temp_func.call::<()>(()).unwrap();
-        Ok(())
-      })
-      .unwrap();
+  ($lua:expr, $ui:expr, $func:expr) => {
+    $lua.scope(|scope| {
+      let lua_ui = scope.create_userdata(LuaUi { ui: $ui })?;
+      $func.call::<()>(lua_ui)
+    })
   };
 }
 
 macro_rules! stylize_element {
-  ($element:ident, $style:ident) => {
+  ($element:ident, $style:ident, $widget:expr) => {
     if let Ok(color_tbl) = $style.get::<mlua::Table>("color") {
-      $element = $element.fill(color_from_lua_table(color_tbl).unwrap());
+      $element = $element.fill(lua_color(color_tbl).context(bad_arg($widget, "style.color"))?);
     }
 
     if let Ok(stroke_tbl) = $style.get::<mlua::Table>("stroke") {
       let width: f32 = stroke_tbl.get(5).unwrap_or(1.0);
       $element = $element.stroke(Stroke::new(
         width,
-        color_from_lua_table(stroke_tbl).unwrap(),
+        lua_color(stroke_tbl).context(bad_arg($widget, "style.stroke"))?,
       ));
     }
 
     if let Ok(rounding) = $style.get::<mlua::Value>("rounding") {
-      $element = $element.rounding(table_into_rounding!(rounding));
+      $element = $element
+        .rounding(table_into_rounding(rounding).context(bad_arg($widget, "style.rounding"))?);
     }
   };
 }
 
-fn into_rich_text(text: mlua::Value) -> RichText {
+fn into_rich_text(text: mlua::Value) -> mlua::Result<RichText> {
   let mut options: Option<mlua::Table> = None;
 
   let text = match text {
-    mlua::Value::String(s) => s.to_str().unwrap().to_string(),
+    mlua::Value::String(s) => s.to_string_lossy(),
     mlua::Value::Table(t) => {
       options = Some(t.clone());
       if let Ok(s) = t.get::<String>("text") {
@@ -203,11 +274,11 @@ fn into_rich_text(text: mlua::Value) -> RichText {
 
   if let Some(options) = options {
     if let Ok(color) = options.get::<mlua::Table>("color") {
-      rich = rich.color(color_from_lua_table(color.clone()).unwrap());
+      rich = rich.color(lua_color(color).context("invalid `color`")?);
     }
 
     if let Ok(color) = options.get::<mlua::Table>("background_color") {
-      rich = rich.background_color(color_from_lua_table(color.clone()).unwrap());
+      rich = rich.background_color(lua_color(color).context("invalid `background_color`")?);
     }
 
     if let Ok(line_height) = options.get::<f32>("line_height") {
@@ -257,7 +328,7 @@ fn into_rich_text(text: mlua::Value) -> RichText {
     }
   }
 
-  rich
+  Ok(rich)
 }
 
 struct LuaVisuals(egui::Visuals);
@@ -289,9 +360,891 @@ impl UserData for LuaVisuals {
   }
 }
 
-struct LuluUiApp {
+/// Viewports whose native window was closed since Lua last showed them.
+#[derive(Default)]
+struct ClosedViewports(std::collections::HashSet<egui::ViewportId>);
+
+/// Renders `func` into a viewport and returns whether it should stay open.
+/// Embedded viewports, used when the backend can not open native windows,
+/// are drawn as an `egui::Window` instead.
+fn render_viewport(
+  lua: &mlua::Lua,
+  ctx: &egui::Context,
+  class: egui::ViewportClass,
+  id: &str,
+  title: &str,
+  func: &mlua::Function,
+) -> mlua::Result<bool> {
+  if class == egui::ViewportClass::Embedded {
+    let mut open = true;
+    egui::Window::new(title)
+      .id(egui::Id::new(("lulu_viewport", id)))
+      .open(&mut open)
+      .show(ctx, |ui| scoped_function_call!(lua, ui, func))
+      .and_then(|res| res.inner)
+      .unwrap_or(Ok(()))
+      .context(in_callback("viewport"))?;
+    Ok(open)
+  } else {
+    egui::CentralPanel::default()
+      .show(ctx, |ui| scoped_function_call!(lua, ui, func))
+      .inner
+      .context(in_callback("viewport"))?;
+    Ok(!ctx.input(|i| i.viewport().close_requested()))
+  }
+}
+
+/// Shows a native viewport for this frame, the same way `egui::Window` shows
+/// an in-canvas one: it stays open for as long as it is shown every frame.
+/// Returns `false` once the user closed it.
+///
+/// Deferred viewports (`deferred = true`) repaint on their own instead of
+/// with the window that opened them, so their errors are reported the next
+/// frame rather than raised.
+fn show_viewport(
+  lua: &mlua::Lua,
+  ctx: &egui::Context,
+  id: String,
+  options: Option<mlua::Table>,
+  func: mlua::Function,
+) -> mlua::Result<bool> {
+  let viewport_id = egui::ViewportId::from_hash_of(("lulu_viewport", &id));
+
+  let closed = lua
+    .app_data_mut::<ClosedViewports>()
+    .is_some_and(|mut closed| closed.0.remove(&viewport_id));
+  if closed {
+    return Ok(false);
+  }
+
+  let mut builder = egui::ViewportBuilder::default().with_title(id.clone());
+  let mut deferred = false;
+  if let Some(options) = &options {
+    builder = window::builder_from_lua(options, builder, &window::root(lua))
+      .context(bad_arg("viewport", "options"))?;
+    deferred = options.get::<Option<bool>>("deferred")?.unwrap_or(false);
+  }
+  let title = builder.title.clone().unwrap_or_else(|| id.clone());
+
+  if !deferred {
+    return ctx.show_viewport_immediate(viewport_id, builder, |ctx, class| {
+      render_viewport(lua, ctx, class, &id, &title, &func)
+    });
+  }
+
+  let lua = lua.clone();
+  ctx.show_viewport_deferred(viewport_id, builder, move |ctx, class| {
+    match render_viewport(&lua, ctx, class, &id, &title, &func) {
+      Ok(true) => {}
+      Ok(false) => {
+        if lua.app_data_ref::<ClosedViewports>().is_none() {
+          lua.set_app_data(ClosedViewports::default());
+        }
+        if let Some(mut closed) = lua.app_data_mut::<ClosedViewports>() {
+          closed.0.insert(viewport_id);
+        }
+      }
+      Err(err) => {
+        if lua.app_data_ref::<ReportedError>().is_none() {
+          lua.set_app_data(ReportedError(ErrorKind::Render, err.to_string()));
+        }
+      }
+    }
+  });
+  Ok(true)
+}
+
+fn width_column(column: &mlua::Table, width: f32) -> mlua::Result<egui_extras::Column> {
+  Ok(if column.get::<Option<bool>>("exact")?.unwrap_or(false) {
+    egui_extras::Column::exact(width)
+  } else {
+    egui_extras::Column::initial(width)
+  })
+}
+
+/// Reads a `ui:table` column definition. `width` is `"auto"`, `"remainder"`
+/// or an initial width in points, made fixed with `exact = true`.
+fn table_column(column: &mlua::Table) -> mlua::Result<egui_extras::Column> {
+  let mut col = match column.get::<mlua::Value>("width")? {
+    mlua::Value::Nil => egui_extras::Column::auto(),
+    mlua::Value::String(s) => match &*s.to_str()? {
+      "auto" => egui_extras::Column::auto(),
+      "remainder" => egui_extras::Column::remainder(),
+      other => {
+        return Err(mlua::Error::runtime(format!(
+          "unknown column width '{}'",
+          other
+        )));
+      }
+    },
+    mlua::Value::Integer(width) => width_column(column, width as f32)?,
+    mlua::Value::Number(width) => width_column(column, width as f32)?,
+    _ => {
+      return Err(mlua::Error::runtime(
+        "expected a number, \"auto\" or \"remainder\"",
+      ));
+    }
+  };
+  if let Some(min) = column.get::<Option<f32>>("min_width")? {
+    col = col.at_least(min);
+  }
+  if let Some(max) = column.get::<Option<f32>>("max_width")? {
+    col = col.at_most(max);
+  }
+  if let Some(clip) = column.get::<Option<bool>>("clip")? {
+    col = col.clip(clip);
+  }
+  Ok(col.resizable(column.get::<Option<bool>>("resizable")?.unwrap_or(true)))
+}
+
+/// Layout for the cells of a column aligned to `start`, `center` or `end`.
+fn cell_layout(align: &str) -> egui::Layout {
+  match align {
+    "center" => egui::Layout::centered_and_justified(egui::Direction::LeftToRight),
+    "end" => egui::Layout::right_to_left(egui::Align::Center),
+    _ => egui::Layout::left_to_right(egui::Align::Center),
+  }
+}
+
+/// Sort order and selected row of a `ui:table`, kept in egui's memory for
+/// tables that do not pass `sort_column` or `selected` themselves.
+#[derive(Clone, Copy, Default)]
+struct TableState {
+  sort: Option<(usize, bool)>,
+  selected: Option<usize>,
+}
+
+/// Shows a data table whose body only calls `cell(ui, row, col)` for the rows
+/// in view, so it stays fast with tens of thousands of rows. Returns
+/// `{ sort = col, ascending = bool, clicked = row, double_clicked = row }`
+/// for this frame, all 1-based and `nil` when nothing happened.
+///
+/// ```lua
+/// ui:table("people", {
+///   rows = 10000,
+///   selected = 3,
+///   sort_column = 1,
+///   sort_ascending = true,
+///   columns = {
+///     { header = "Name", width = "remainder", sortable = true },
+///     { header = "Age", width = 60, align = "end" },
+///   },
+/// }, function(ui, row, col) ui:label(row .. ":" .. col) end)
+/// ```
+fn show_table(
+  lua: &mlua::Lua,
+  ui: &mut egui::Ui,
+  options: mlua::Table,
+  cell: mlua::Function,
+) -> mlua::Result<mlua::Table> {
+  let columns: Vec<mlua::Table> = options
+    .get::<Option<Vec<mlua::Table>>>("columns")?
+    .unwrap_or_default();
+  let rows = options.get::<Option<usize>>("rows")?.unwrap_or(0);
+  let row_height = options.get::<Option<f32>>("row_height")?.unwrap_or(18.0);
+  let header_height = options.get::<Option<f32>>("header_height")?.unwrap_or(20.0);
+  let state_id = ui.id();
+  let mut state = ui.data(|d| d.get_temp::<TableState>(state_id).unwrap_or_default());
+  if let Some(col) = options.get::<Option<usize>>("sort_column")? {
+    let ascending = options.get::<Option<bool>>("sort_ascending")?.unwrap_or(true);
+    state.sort = Some((col, ascending));
+  }
+  if let Some(row) = options.get::<Option<usize>>("selected")? {
+    state.selected = Some(row);
+  }
+
+  let mut headers = Vec::with_capacity(columns.len());
+  let mut layouts = Vec::with_capacity(columns.len());
+  let mut builder = egui_extras::TableBuilder::new(ui)
+    .striped(options.get::<Option<bool>>("striped")?.unwrap_or(true))
+    .resizable(options.get::<Option<bool>>("resizable")?.unwrap_or(true))
+    .sense(egui::Sense::click());
+  for (i, column) in columns.iter().enumerate() {
+    builder = builder
+      .column(table_column(column).context(bad_arg("table", &format!("columns[{}]", i + 1)))?);
+    let align = column.get::<Option<String>>("align")?.unwrap_or_default();
+    layouts.push(cell_layout(&align));
+    headers.push((
+      column.get::<Option<String>>("header")?.unwrap_or_default(),
+      column.get::<Option<bool>>("sortable")?.unwrap_or(false),
+    ));
+  }
+  if let Some(height) = options.get::<Option<f32>>("max_height")? {
+    builder = builder.max_scroll_height(height);
+  }
+  if let Some(row) = options.get::<Option<usize>>("scroll_to_row")? {
+    builder = builder.scroll_to_row(row.saturating_sub(1), None);
+  }
+
+  let mut sort = None;
+  let mut clicked = None;
+  let mut double_clicked = None;
+  let mut error = None;
+
+  let table = builder.header(header_height, |mut header| {
+    for (i, (text, sortable)) in headers.iter().enumerate() {
+      header.col(|ui| {
+        ui.with_layout(layouts[i], |ui| {
+          if !sortable {
+            ui.strong(text);
+            return;
+          }
+          let arrow = match state.sort {
+            Some((col, true)) if col == i + 1 => " ⏶",
+            Some((col, false)) if col == i + 1 => " ⏷",
+            _ => "",
+          };
+          let label = egui::Label::new(RichText::new(format!("{}{}", text, arrow)).strong())
+            .sense(egui::Sense::click());
+          if ui.add(label).clicked() {
+            sort = Some(i + 1);
+          }
+        });
+      });
+    }
+  });
+
+  table.body(|body| {
+    body.rows(row_height, rows, |mut row| {
+      let index = row.index() + 1;
+      row.set_selected(state.selected == Some(index));
+      for (i, layout) in layouts.iter().enumerate() {
+        row.col(|ui| {
+          if error.is_some() {
+            return;
+          }
+          let result = ui
+            .with_layout(*layout, |ui| {
+              lua.scope(|scope| {
+                let lua_ui = scope.create_userdata(LuaUi { ui })?;
+                cell.call::<()>((lua_ui, index, i + 1))
+              })
+            })
+            .inner;
+          if let Err(err) = result {
+            error = Some(err);
+          }
+        });
+      }
+      let response = row.response();
+      if response.double_clicked() {
+        double_clicked = Some(index);
+      } else if response.clicked() {
+        clicked = Some(index);
+      }
+    });
+  });
+
+  if let Some(err) = error {
+    return Err(err).context(in_callback("table"));
+  }
+
+  if let Some(col) = sort {
+    let ascending = !matches!(state.sort, Some((current, true)) if current == col);
+    state.sort = Some((col, ascending));
+  }
+  if let Some(row) = double_clicked.or(clicked) {
+    state.selected = Some(row);
+  }
+  ui.data_mut(|d| d.insert_temp(state_id, state));
+
+  let result = lua.create_table()?;
+  result.set("sort", sort)?;
+  result.set("ascending", sort.and(state.sort).map(|(_, ascending)| ascending))?;
+  result.set("clicked", clicked)?;
+  result.set("double_clicked", double_clicked)?;
+  Ok(result)
+}
+
+/// What a `ui:virtual_list` keeps in egui's memory between frames.
+#[derive(Clone, Default)]
+struct ListState {
+  /// Height of each row by position, when rows have no fixed `row_height`.
+  heights: Vec<f32>,
+  /// Top of each row by position, plus the bottom of the last one, so the
+  /// first row in view is found with a binary search.
+  offsets: Vec<f32>,
+  /// The key of each row by position, for the rows shown so far, so heights
+  /// and the anchor follow rows that move.
+  keys: Vec<Option<egui::Id>>,
+  /// The key and position of the first row in view, and how far into it the
+  /// view starts, used to keep that row in place when rows above it change.
+  anchor: Option<(egui::Id, usize, f32)>,
+}
+
+impl ListState {
+  fn update_offsets(&mut self, spacing: f32) {
+    self.offsets.clear();
+    self.offsets.reserve(self.heights.len() + 1);
+    let mut top = 0.0;
+    for height in &self.heights {
+      self.offsets.push(top);
+      top += height + spacing;
+    }
+    self.offsets.push(top);
+  }
+
+  /// Carries the keys, heights and anchor over to `rows` rows, taking the
+  /// count to have changed by rows inserted or removed in one place. Only a
+  /// few keys are looked up: the rows shown before are binary searched for
+  /// the first one that moved and, after a removal, the first one that was
+  /// not removed. Returns `false` when the anchor row is not where that puts
+  /// it, as rows changed in more than one place.
+  fn resize(
+    &mut self,
+    rows: usize,
+    key: &Option<mlua::Function>,
+    estimate: f32,
+    variable: bool,
+  ) -> mlua::Result<bool> {
+    let old_rows = self.keys.len();
+    let known: Vec<usize> = (0..old_rows).filter(|&row| self.keys[row].is_some()).collect();
+    let keys = &self.keys;
+    let holds = |row: usize, old: usize| -> mlua::Result<bool> {
+      Ok(row < rows && Some(row_id(row + 1, key)?) == keys[old])
+    };
+
+    let moved = partition_point(&known, |row| holds(row, row))?;
+    let start = known.get(moved).copied().unwrap_or(old_rows);
+    let end = if rows < old_rows {
+      let removed = old_rows - rows;
+      let kept = moved + partition_point(&known[moved..], |row| Ok(!(row >= removed && holds(row - removed, row)?)))?;
+      known.get(kept).copied().unwrap_or(old_rows)
+    } else {
+      start
+    };
+    let new_row = |row: usize| -> Option<usize> {
+      let row = if row < start {
+        row
+      } else if row < end {
+        return None;
+      } else {
+        (row + rows).checked_sub(old_rows)?
+      };
+      (row < rows).then_some(row)
+    };
+
+    let mut new_keys = vec![None; rows];
+    let mut heights = if variable { vec![estimate; rows] } else { Vec::new() };
+    for (row, id) in self.keys.iter().enumerate() {
+      if let Some(to) = new_row(row) {
+        new_keys[to] = *id;
+        if variable && let Some(height) = self.heights.get(row) {
+          heights[to] = *height;
+        }
+      }
+    }
+    self.keys = new_keys;
+    self.heights = heights;
+
+    let Some((id, row, into)) = self.anchor else {
+      return Ok(true);
+    };
+    match new_row(row) {
+      Some(row) if row_id(row + 1, key)? == id => {
+        self.anchor = Some((id, row, into));
+        Ok(true)
+      }
+      _ => Ok(false),
+    }
+  }
+
+  /// Looks up the key of every row to carry heights and the anchor along,
+  /// for when rows were reordered or changed in more than one place.
+  fn rekey(
+    &mut self,
+    rows: usize,
+    key: &Option<mlua::Function>,
+    estimate: f32,
+    variable: bool,
+  ) -> mlua::Result<()> {
+    let mut measured = HashMap::default();
+    for (row, id) in self.keys.iter().enumerate() {
+      if let (Some(id), Some(height)) = (id, self.heights.get(row)) {
+        measured.insert(*id, *height);
+      }
+    }
+
+    let anchor = self.anchor.take();
+    self.keys.clear();
+    self.heights.clear();
+    for row in 0..rows {
+      let id = row_id(row + 1, key)?;
+      if let Some((anchor_id, _, into)) = anchor
+        && anchor_id == id
+      {
+        self.anchor = Some((id, row, into));
+      }
+      if variable {
+        self.heights.push(measured.get(&id).copied().unwrap_or(estimate));
+      }
+      self.keys.push(Some(id));
+    }
+    Ok(())
+  }
+}
+
+/// The index of the first item of `items` for which `pred` is false, with
+/// `pred` true for every item before it.
+fn partition_point(
+  items: &[usize],
+  mut pred: impl FnMut(usize) -> mlua::Result<bool>,
+) -> mlua::Result<usize> {
+  let (mut low, mut high) = (0, items.len());
+  while low < high {
+    let mid = (low + high) / 2;
+    if pred(items[mid])? {
+      low = mid + 1;
+    } else {
+      high = mid;
+    }
+  }
+  Ok(low)
+}
+
+/// The id scope of `row`, from `key(row)` when the list has keys so widget
+/// state and measured heights follow the item rather than its position.
+fn row_id(row: usize, key: &Option<mlua::Function>) -> mlua::Result<egui::Id> {
+  Ok(match key {
+    Some(key) => egui::Id::new(key.call::<String>(row)?),
+    None => egui::Id::new(row),
+  })
+}
+
+/// Calls `render(ui, row)` inside the id scope `id` of the row.
+fn render_row(
+  lua: &mlua::Lua,
+  ui: &mut egui::Ui,
+  id: egui::Id,
+  row: usize,
+  render: &mlua::Function,
+) -> mlua::Result<()> {
+  ui.push_id(id, |ui| {
+    lua.scope(|scope| {
+      let lua_ui = scope.create_userdata(LuaUi { ui })?;
+      render.call::<()>((lua_ui, row))
+    })
+  })
+  .inner
+}
+
+/// Shows a scrolling list that only calls `render(ui, row)` for the rows in
+/// view. With a `row_height` every row is assumed to be that tall; without
+/// one, rows are measured as they are shown and rows not seen yet are
+/// estimated at `estimated_row_height`. With a `key`, the first row in view
+/// stays in place when rows are inserted or removed above it.
+///
+/// ```lua
+/// ui:virtual_list("log", {
+///   rows = 100000,
+///   row_height = 16,
+///   scroll_to = 500,
+///   key = function(row) return lines[row].id end,
+/// }, function(ui, row) ui:monospace(lines[row].text) end)
+/// ```
+fn show_virtual_list(
+  lua: &mlua::Lua,
+  ui: &mut egui::Ui,
+  options: mlua::Table,
+  render: mlua::Function,
+) -> mlua::Result<()> {
+  let rows = options.get::<Option<usize>>("rows")?.unwrap_or(0);
+  let row_height = options.get::<Option<f32>>("row_height")?;
+  let key = options.get::<Option<mlua::Function>>("key")?;
+  let scroll_to = options.get::<Option<usize>>("scroll_to")?;
+  let estimate = options
+    .get::<Option<f32>>("estimated_row_height")?
+    .unwrap_or(18.0);
+  let spacing = ui.spacing().item_spacing.y;
+  let state_id = ui.id();
+  let mut state = ui.data(|d| d.get_temp::<ListState>(state_id).unwrap_or_default());
+
+  // Rows moved when the count changed or the anchor row holds another key.
+  // Every key is only looked up when that cannot be narrowed down to one
+  // inserted or removed range.
+  let variable = row_height.is_none();
+  let mut moved = false;
+  if state.keys.len() != rows || (variable && state.heights.len() != rows) {
+    moved = true;
+    if !state.resize(rows, &key, estimate, variable)? {
+      state.rekey(rows, &key, estimate, variable)?;
+    }
+  } else if let Some((id, row, _)) = state.anchor
+    && (row >= rows || row_id(row + 1, &key)? != id)
+  {
+    moved = true;
+    state.rekey(rows, &key, estimate, variable)?;
+  }
+  if variable && (moved || state.offsets.len() != rows + 1) {
+    state.update_offsets(spacing);
+  }
+
+  let top_of = |state: &ListState, row: usize| match row_height {
+    Some(height) => row as f32 * (height + spacing),
+    None => state.offsets[row],
+  };
+
+  let mut scroll_area = egui::ScrollArea::vertical()
+    .auto_shrink([false, options.get::<Option<bool>>("auto_shrink")?.unwrap_or(false)])
+    .stick_to_bottom(options.get::<Option<bool>>("stick_to_bottom")?.unwrap_or(false));
+  if let Some(height) = options.get::<Option<f32>>("max_height")? {
+    scroll_area = scroll_area.max_height(height);
+  }
+  if let Some(row) = scroll_to {
+    let row = row.clamp(1, rows.max(1)) - 1;
+    scroll_area = scroll_area.vertical_scroll_offset(top_of(&state, row.min(rows)));
+  } else if moved
+    && key.is_some()
+    && let Some((_, row, into)) = state.anchor
+  {
+    scroll_area = scroll_area.vertical_scroll_offset(top_of(&state, row) + into);
+  }
+
+  let mut remeasured = false;
+  let result = scroll_area
+    .show_viewport(ui, |ui, viewport| -> mlua::Result<()> {
+      let origin = ui.max_rect().min;
+      let width = ui.available_width();
+      ui.set_height((top_of(&state, rows) - spacing).max(0.0));
+
+      let first = match row_height {
+        Some(height) => (viewport.min.y / (height + spacing)).floor().max(0.0) as usize,
+        None => state.offsets[..rows]
+          .partition_point(|top| *top <= viewport.min.y)
+          .saturating_sub(1),
+      };
+      state.anchor = None;
+      for row in first..rows {
+        let top = top_of(&state, row);
+        if top > viewport.max.y {
+          break;
+        }
+        let height = row_height.unwrap_or_else(|| state.heights[row]);
+        let id = row_id(row + 1, &key)?;
+        state.keys[row] = Some(id);
+        if state.anchor.is_none() {
+          state.anchor = Some((id, row, viewport.min.y - top));
+        }
+
+        let rect = egui::Rect::from_min_size(origin + egui::vec2(0.0, top), egui::vec2(width, height));
+        let response = ui.allocate_ui_at_rect(rect, |ui| render_row(lua, ui, id, row + 1, &render));
+        response.inner?;
+        if variable {
+          let measured = response.response.rect.height();
+          if (measured - height).abs() > 0.5 {
+            state.heights[row] = measured;
+            remeasured = true;
+          }
+        }
+      }
+      Ok(())
+    })
+    .inner;
+
+  if remeasured {
+    state.update_offsets(spacing);
+    ui.ctx().request_repaint();
+  }
+  ui.data_mut(|d| d.insert_temp(state_id, state));
+  result.context(in_callback("virtual_list"))
+}
+
+/// Key in egui's memory of the `ModalStack`.
+const MODAL_STACK: &str = "lulu_modal_stack";
+
+/// The modals shown by `ui:modal`, in the order they were shown so the last
+/// one is on top.
+#[derive(Clone, Default)]
+struct ModalStack {
+  shown: Vec<egui::Id>,
+  /// The topmost modal of the last frame, which gets this frame's keyboard
+  /// input.
+  top: Option<egui::Id>,
+  /// This frame's keyboard events, held back from the widgets behind `top`.
+  events: Vec<egui::Event>,
+}
+
+fn is_keyboard_event(event: &egui::Event) -> bool {
+  matches!(
+    event,
+    egui::Event::Key { .. }
+      | egui::Event::Text(_)
+      | egui::Event::Paste(_)
+      | egui::Event::Copy
+      | egui::Event::Cut
+      | egui::Event::CompositionStart
+      | egui::Event::CompositionUpdate(_)
+      | egui::Event::CompositionEnd(_)
+  )
+}
+
+/// Takes this frame's keyboard events out of the input when a modal was
+/// shown last frame, for `ui:modal` to hand to the topmost one.
+fn hold_modal_input(ctx: &egui::Context) {
+  let id = egui::Id::new(MODAL_STACK);
+  let mut stack = ctx.data(|d| d.get_temp::<ModalStack>(id)).unwrap_or_default();
+  stack.top = stack.shown.pop();
+  stack.shown.clear();
+  stack.events = match stack.top {
+    Some(_) => ctx.input_mut(|i| {
+      let (keyboard, rest) = i.events.drain(..).partition(is_keyboard_event);
+      i.events = rest;
+      keyboard
+    }),
+    None => Vec::new(),
+  };
+  ctx.data_mut(|d| d.insert_temp(id, stack));
+}
+
+/// The key of a tab being dragged out of a `ui:tab_bar`.
+struct TabDrag(String);
+
+/// Shows a row of tabs, each `{ key, title, closable }`, with the one whose
+/// key is `options.selected` highlighted. With `options.draggable` tabs can
+/// be dragged onto any tab bar or `ui:dock_target`. Returns
+/// `{ selected = key, closed = key, dropped = { key, index } }` for this
+/// frame, where `index` is the 1-based position a dragged tab was dropped at.
+fn show_tab_bar(
+  lua: &mlua::Lua,
+  ui: &mut egui::Ui,
+  id: egui::Id,
+  tabs: mlua::Table,
+  options: Option<mlua::Table>,
+) -> mlua::Result<mlua::Table> {
+  let mut selected = None;
+  let mut draggable = false;
+  if let Some(options) = &options {
+    selected = options.get::<Option<String>>("selected")?;
+    draggable = options.get::<Option<bool>>("draggable")?.unwrap_or(false);
+  }
+
+  let result = lua.create_table()?;
+  ui.horizontal(|ui| -> mlua::Result<()> {
+    let padding = ui.spacing().button_padding;
+    let mut height = ui.spacing().interact_size.y;
+    let mut drop: Option<(String, usize)> = None;
+
+    let count = tabs.raw_len();
+    for (index, tab) in tabs.sequence_values::<mlua::Table>().enumerate() {
+      let tab = tab?;
+      let key: String = tab.get("key")?;
+      let title = tab.get::<Option<String>>("title")?.unwrap_or_else(|| key.clone());
+      let closable = tab.get::<Option<bool>>("closable")?.unwrap_or(false);
+      let is_selected = selected.as_deref() == Some(key.as_str());
+
+      let galley = WidgetText::from(title.as_str()).into_galley(
+        ui,
+        Some(false),
+        f32::INFINITY,
+        TextStyle::Button,
+      );
+      let close_width = if closable { galley.size().y } else { 0.0 };
+      let size = Vec2::new(
+        galley.size().x + close_width + padding.x * 2.0,
+        galley.size().y + padding.y * 2.0,
+      );
+      height = height.max(size.y);
+      let (_, rect) = ui.allocate_space(size);
+      let tab_id = id.with(&key);
+      let response = ui.interact(rect, tab_id, Sense::click_and_drag());
+      if draggable {
+        response.dnd_set_drag_payload(TabDrag(key.clone()));
+        if response.dragged() {
+          egui::show_tooltip_at_pointer(ui.ctx(), tab_id.with("drag"), |ui| {
+            ui.label(title.as_str())
+          });
+        }
+      }
+
+      let visuals = ui.style().interact_selectable(&response, is_selected);
+      if is_selected || response.hovered() {
+        ui.painter().rect_filled(rect, visuals.rounding, visuals.weak_bg_fill);
+      }
+      let text_pos = rect.left_center() + Vec2::new(padding.x, -galley.size().y / 2.0);
+      ui.painter().galley(text_pos, galley, visuals.text_color());
+
+      let mut closed = response.middle_clicked() && closable;
+      if closable {
+        let close_rect = Rect::from_min_size(
+          egui::pos2(rect.right() - padding.x - close_width, rect.top()),
+          Vec2::new(close_width, rect.height()),
+        );
+        let close = ui.interact(close_rect, tab_id.with("close"), Sense::click());
+        let color = if close.hovered() {
+          ui.visuals().error_fg_color
+        } else {
+          visuals.text_color()
+        };
+        ui.painter().text(
+          close_rect.center(),
+          Align2::CENTER_CENTER,
+          "×",
+          TextStyle::Button.resolve(ui.style()),
+          color,
+        );
+        closed |= close.clicked() || close.middle_clicked();
+      }
+
+      if closed {
+        result.set("closed", key.as_str())?;
+      } else if response.clicked() {
+        result.set("selected", key.as_str())?;
+      }
+
+      if response.dnd_hover_payload::<TabDrag>().is_some() {
+        let pointer = ui.ctx().pointer_hover_pos().unwrap_or(rect.center());
+        let before = pointer.x < rect.center().x;
+        let x = if before { rect.left() } else { rect.right() };
+        ui.painter().vline(x, rect.y_range(), ui.visuals().selection.stroke);
+        if let Some(payload) = response.dnd_release_payload::<TabDrag>() {
+          drop = Some((payload.0.clone(), if before { index + 1 } else { index + 2 }));
+        }
+      }
+    }
+
+    // The rest of the row takes tabs dropped after the last one.
+    let (_, rest) = ui.allocate_space(Vec2::new(ui.available_width(), height));
+    let rest = ui.interact(rest, id.with("rest"), Sense::hover());
+    if let Some(payload) = rest.dnd_release_payload::<TabDrag>() {
+      drop = Some((payload.0.clone(), count + 1));
+    }
+
+    if let Some((key, index)) = drop {
+      let dropped = lua.create_table()?;
+      dropped.set("key", key)?;
+      dropped.set("index", index)?;
+      result.set("dropped", dropped)?;
+    }
+    Ok(())
+  })
+  .inner?;
+
+  Ok(result)
+}
+
+/// Where a tab dropped at `pointer` on `rect` goes: into the group shown
+/// there ("center"), or into a new group split off one of its edges.
+fn dock_zone(rect: Rect, pointer: egui::Pos2) -> (&'static str, Rect) {
+  let x = (pointer.x - rect.left()) / rect.width();
+  let y = (pointer.y - rect.top()) / rect.height();
+  let half = rect.size() / 2.0;
+  if x < 0.25 {
+    ("left", Rect::from_min_size(rect.min, Vec2::new(half.x, rect.height())))
+  } else if x > 0.75 {
+    ("right", Rect::from_min_max(egui::pos2(rect.center().x, rect.top()), rect.max))
+  } else if y < 0.25 {
+    ("top", Rect::from_min_size(rect.min, Vec2::new(rect.width(), half.y)))
+  } else if y > 0.75 {
+    ("bottom", Rect::from_min_max(egui::pos2(rect.left(), rect.center().y), rect.max))
+  } else {
+    ("center", rect)
+  }
+}
+
+pub struct LuluUiApp {
   lulu: Lulu,
-  init_error: Option<String>,
+  main: Option<mlua::Value>,
+  sources: Sources,
+  error: Option<AppError>,
+  dev: Option<DevReload>,
+  /// Shapes of the last frame that rendered without errors, painted under the
+  /// error overlay.
+  last_frame: Vec<egui::epaint::ClippedShape>,
+}
+
+/// Repaints when a Rust future awaited by a Lua task completes.
+struct RepaintWaker(egui::Context);
+
+impl std::task::Wake for RepaintWaker {
+  fn wake(self: std::sync::Arc<Self>) {
+    self.0.request_repaint();
+  }
+}
+
+/// Set as Lua app data by `request_exit` once the app should quit.
+struct ExitCode(i32);
+
+/// Set as Lua app data by `report_error` for errors Lua caught itself, such
+/// as ones raised by event handlers.
+struct ReportedError(ErrorKind, String);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrorKind {
+  Init,
+  Render,
+  Event,
+  Task,
+  Timer,
+}
+
+impl ErrorKind {
+  fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "init" => Some(Self::Init),
+      "render" => Some(Self::Render),
+      "event" => Some(Self::Event),
+      "task" => Some(Self::Task),
+      "timer" => Some(Self::Timer),
+      _ => None,
+    }
+  }
+
+  fn title(self) -> &'static str {
+    match self {
+      Self::Init => "Lua Initialization Error",
+      Self::Render => "Lua Render Error",
+      Self::Event => "Lua Event Handler Error",
+      Self::Task => "Lua Async Task Error",
+      Self::Timer => "Lua Timer Error",
+    }
+  }
+}
+
+/// A Lua error with its traceback split into frames, pointing at the original
+/// `.lua` sources where they are known.
+pub struct AppError {
+  pub kind: ErrorKind,
+  pub message: String,
+  pub traceback: Vec<String>,
+}
+
+impl AppError {
+  fn new(kind: ErrorKind, err: &str, lulu: &Lulu, sources: &Sources) -> Self {
+    let err = sources.rewrite(lulu, err);
+
+    let mut message = Vec::new();
+    let mut traceback = Vec::new();
+    let mut in_traceback = false;
+    for line in err.lines() {
+      if line.trim() == "stack traceback:" {
+        in_traceback = true;
+      } else if in_traceback && line.starts_with('\t') {
+        traceback.push(line.trim().to_string());
+      } else {
+        in_traceback = false;
+        message.push(line);
+      }
+    }
+
+    Self {
+      kind,
+      message: message.join("\n").trim().to_string(),
+      traceback,
+    }
+  }
+}
+
+impl std::fmt::Display for AppError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}: {}", self.kind.title(), self.message)?;
+    if !self.traceback.is_empty() {
+      write!(f, "\nstack traceback:")?;
+      for frame in &self.traceback {
+        write!(f, "\n\t{}", frame)?;
+      }
+    }
+    Ok(())
+  }
 }
 
 #[derive(Clone)]
@@ -310,6 +1263,44 @@ impl UserData for LuaUiResponse {
       this.res.scroll_to_me(align.map(|a| to_align(&a)));
       Ok(())
     });
+    methods.add_method_mut("on_hover_text", |_lua, this: &mut LuaUiResponse, text: mlua::Value| {
+      let text = into_rich_text(text).context("response:on_hover_text: invalid `text`")?;
+      this.res = this.res.clone().on_hover_text(text);
+      Ok(())
+    });
+    methods.add_method_mut(
+      "on_disabled_hover_text",
+      |_lua, this: &mut LuaUiResponse, text: mlua::Value| {
+        let text =
+          into_rich_text(text).context("response:on_disabled_hover_text: invalid `text`")?;
+        this.res = this.res.clone().on_disabled_hover_text(text);
+        Ok(())
+      },
+    );
+    methods.add_method_mut(
+      "on_hover_ui",
+      |lua, this: &mut LuaUiResponse, func: mlua::Function| {
+        let mut result = Ok(());
+        this.res = this
+          .res
+          .clone()
+          .on_hover_ui(|ui| result = scoped_function_call!(lua, ui, func));
+        result.context("in response:on_hover_ui callback")
+      },
+    );
+    // Returns whether the menu is open.
+    methods.add_method_mut(
+      "context_menu",
+      |lua, this: &mut LuaUiResponse, func: mlua::Function| {
+        let mut result = Ok(());
+        let open = this
+          .res
+          .context_menu(|ui| result = scoped_function_call!(lua, ui, func))
+          .is_some();
+        result.context("in response:context_menu callback")?;
+        Ok(open)
+      },
+    );
   }
 
   fn add_fields<F: mlua::UserDataFields<Self>>(fields: &mut F) {
@@ -359,6 +1350,10 @@ impl UserData for LuaUiResponse {
     fields.add_field_method_get("is_pointer_button_down_on", |_, this| {
       Ok(this.res.is_pointer_button_down_on())
     });
+    fields.add_field_method_get("rect", |_, this| {
+      let rect = this.res.rect;
+      Ok(vec![rect.min.x, rect.min.y, rect.max.x, rect.max.y])
+    });
   }
 }
 
@@ -371,10 +1366,10 @@ impl<'ui> UserData for LuaUi<'ui> {
     methods.add_method_mut(
       "button",
       |_lua, this: &mut LuaUi, (text, style): (mlua::Value, Option<mlua::Table>)| {
-        let mut button = Button::new(into_rich_text(text));
+        let mut button = Button::new(into_rich_text(text).context(bad_arg("button", "text"))?);
 
         if let Some(style_table) = style {
-          stylize_element!(button, style_table);
+          stylize_element!(button, style_table, "button");
         }
 
         let res: Response = this.ui.add(button);
@@ -388,44 +1383,44 @@ impl<'ui> UserData for LuaUi<'ui> {
         Ok(LuaUiResponse {
           res: this
             .ui
-            .colored_label(color_from_lua_table(color).unwrap(), text),
+            .colored_label(lua_color(color).context(bad_arg("colored_label", "color"))?, text),
           value: None,
         })
       },
     );
     methods.add_method_mut("label", |_lua, this: &mut LuaUi, text: mlua::Value| {
       Ok(LuaUiResponse {
-        res: this.ui.label(into_rich_text(text)),
+        res: this.ui.label(into_rich_text(text).context(bad_arg("label", "text"))?),
         value: None,
       })
     });
     methods.add_method_mut("heading", |_lua, this: &mut LuaUi, text: mlua::Value| {
       Ok(LuaUiResponse {
-        res: this.ui.heading(into_rich_text(text)),
+        res: this.ui.heading(into_rich_text(text).context(bad_arg("heading", "text"))?),
         value: None,
       })
     });
     methods.add_method_mut("small", |_lua, this: &mut LuaUi, text: mlua::Value| {
       Ok(LuaUiResponse {
-        res: this.ui.small(into_rich_text(text)),
+        res: this.ui.small(into_rich_text(text).context(bad_arg("small", "text"))?),
         value: None,
       })
     });
     methods.add_method_mut("monospace", |_lua, this: &mut LuaUi, text: mlua::Value| {
       Ok(LuaUiResponse {
-        res: this.ui.monospace(into_rich_text(text)),
+        res: this.ui.monospace(into_rich_text(text).context(bad_arg("monospace", "text"))?),
         value: None,
       })
     });
     methods.add_method_mut("strong", |_lua, this: &mut LuaUi, text: mlua::Value| {
       Ok(LuaUiResponse {
-        res: this.ui.strong(into_rich_text(text)),
+        res: this.ui.strong(into_rich_text(text).context(bad_arg("strong", "text"))?),
         value: None,
       })
     });
     methods.add_method_mut("weak", |_lua, this: &mut LuaUi, text: mlua::Value| {
       Ok(LuaUiResponse {
-        res: this.ui.weak(into_rich_text(text)),
+        res: this.ui.weak(into_rich_text(text).context(bad_arg("weak", "text"))?),
         value: None,
       })
     });
@@ -470,14 +1465,16 @@ impl<'ui> UserData for LuaUi<'ui> {
             size.1 = get_size_attrib!(this.ui, w);
           }
           if let Ok(text_color) = options_table.get::<mlua::Table>("text_color") {
-            textedit = textedit.text_color(color_from_lua_table(text_color).unwrap());
+            textedit =
+              textedit.text_color(lua_color(text_color).context(bad_arg("text_edit", "text_color"))?);
           }
           if let Ok(char_limit) = options_table.get::<usize>("char_limit") {
             textedit = textedit.char_limit(char_limit);
           }
           if let Ok(id_opt) = options_table.get::<String>("id") {
-            id = Some(egui::Id::new(id_opt));
-            textedit = textedit.id(id.unwrap());
+            let id_opt = egui::Id::new(id_opt);
+            id = Some(id_opt);
+            textedit = textedit.id(id_opt);
           }
           if let Ok(placeholder) = options_table.get::<String>("placeholder") {
             textedit = textedit.hint_text(placeholder);
@@ -518,7 +1515,8 @@ impl<'ui> UserData for LuaUi<'ui> {
           }
 
           if let Ok(margin) = options_table.get::<mlua::Value>("margin") {
-            textedit = textedit.margin(table_into_margin!(margin));
+            textedit =
+              textedit.margin(table_into_margin(margin).context(bad_arg("text_edit", "margin"))?);
           }
         }
 
@@ -554,7 +1552,7 @@ impl<'ui> UserData for LuaUi<'ui> {
           }
 
           mlua::Value::String(s) => {
-            let src = s.to_str().unwrap();
+            let src = s.to_str()?;
 
             if src.starts_with("http://") || src.starts_with("https://") {
               Image::from_uri(src.to_string())
@@ -584,7 +1582,10 @@ impl<'ui> UserData for LuaUi<'ui> {
             img = img.maintain_aspect_ratio(maintain_aspect_ratio);
           }
           if let Ok(fit) = options.get::<mlua::Table>("fit_to") {
-            let size = Vec2::new(fit.get(1).unwrap(), fit.get(2).unwrap());
+            let size = Vec2::new(
+              fit.get(1).context(bad_arg("image", "fit_to"))?,
+              fit.get(2).context(bad_arg("image", "fit_to"))?,
+            );
             img = img.fit_to_exact_size(size);
           }
           if let Ok(width) = options.get::<f32>("width") {
@@ -599,11 +1600,14 @@ impl<'ui> UserData for LuaUi<'ui> {
             }
           }
           if let Ok(rotate) = options.get::<mlua::Table>("rotate") {
-            let origin = Vec2::new(rotate.get(1).unwrap(), rotate.get(2).unwrap());
-            img = img.rotate(rotate.get(3).unwrap(), origin);
+            let origin = Vec2::new(
+              rotate.get(1).context(bad_arg("image", "rotate"))?,
+              rotate.get(2).context(bad_arg("image", "rotate"))?,
+            );
+            img = img.rotate(rotate.get(3).context(bad_arg("image", "rotate"))?, origin);
           }
           if let Ok(rounding) = options.get::<mlua::Value>("rounding") {
-            img = img.rounding(table_into_rounding!(rounding));
+            img = img.rounding(table_into_rounding(rounding).context(bad_arg("image", "rounding"))?);
           }
           if let Ok(spinner) = options.get::<bool>("spinner") {
             img = img.show_loading_spinner(spinner);
@@ -617,8 +1621,8 @@ impl<'ui> UserData for LuaUi<'ui> {
         if let Some(xy) = xy {
           let size = img.size();
 
-          let x = xy.get(1).unwrap();
-          let y = xy.get(2).unwrap();
+          let x = xy.get(1).context(bad_arg("image", "at"))?;
+          let y = xy.get(2).context(bad_arg("image", "at"))?;
 
           let (w, h) = if let Some(size) = size {
             (size.x, size.y)
@@ -657,29 +1661,21 @@ impl<'ui> UserData for LuaUi<'ui> {
         let mut keys: Vec<_> = values.keys().cloned().collect();
         keys.sort();
 
-        let res = egui::ComboBox::from_label(label)
+        let combo = egui::ComboBox::from_label(label)
           .selected_text(values.get(&selected_key).unwrap_or(&selected_key))
-          .show_ui(this.ui, |ui| {
+          .show_ui(this.ui, |ui| -> mlua::Result<()> {
             for key in keys {
-              let value = values.get(&key).unwrap();
+              let value = &values[&key];
               if let Some(func) = func.clone() {
-                let selected_key_old = selected_key_old.clone();
-                let new_selected = lua
-                  .scope(|_scope| {
-                    let lua_ui = _scope.create_userdata(LuaUi { ui }).unwrap();
-                    let temp_func = _scope
-                      .create_function(move |_lua, selected_key_old: String| {
-                        func.call::<Option<String>>((
-                          lua_ui.clone(),
-                          selected_key_old,
-                          key.clone(),
-                          value.clone(),
-                        ))
-                      })
-                      .unwrap();
-                    temp_func.call::<Option<String>>(selected_key_old)
-                  })
-                  .unwrap();
+                let new_selected = lua.scope(|scope| {
+                  let lua_ui = scope.create_userdata(LuaUi { ui })?;
+                  func.call::<Option<String>>((
+                    lua_ui,
+                    selected_key_old.clone(),
+                    key.clone(),
+                    value.clone(),
+                  ))
+                })?;
                 if let Some(new_selected) = new_selected {
                   selected_key = new_selected
                 }
@@ -687,8 +1683,14 @@ impl<'ui> UserData for LuaUi<'ui> {
                 ui.selectable_value(&mut selected_key, key.clone(), value);
               }
             }
-          })
-          .response;
+            Ok(())
+          });
+
+        combo
+          .inner
+          .unwrap_or(Ok(()))
+          .context(in_callback("combobox"))?;
+        let res = combo.response;
 
         let value = if selected_key_old != selected_key {
           let table = lua.create_table()?;
@@ -794,109 +1796,61 @@ impl<'ui> UserData for LuaUi<'ui> {
     methods.add_method_mut(
       "horizontal",
       |lua, this: &mut LuaUi, func: mlua::Function| {
-        this.ui.horizontal(|ui| {
-          lua
-            .scope(|_scope| {
-              let lua_ui = _scope.create_userdata(LuaUi { ui }).unwrap();
-              let temp_func = _scope
-                .create_function(move |_lua, ()| func.call::<()>(lua_ui.clone()))
-                .unwrap();
-              temp_func.call::<()>(()).unwrap();
-              Ok(())
-            })
-            .unwrap();
-        });
-        Ok(())
+        this
+          .ui
+          .horizontal(|ui| scoped_function_call!(lua, ui, func))
+          .inner
+          .context(in_callback("horizontal"))
       },
     );
 
     methods.add_method_mut("vertical", |lua, this: &mut LuaUi, func: mlua::Function| {
-      this.ui.vertical(|ui| {
-        lua
-          .scope(|scope| {
-            let lua_ui = scope.create_userdata(LuaUi { ui }).unwrap();
-            let temp_func = scope
-              .create_function(move |_lua, ()| func.call::<()>(lua_ui.clone()))
-              .unwrap();
-            temp_func.call::<()>(()).unwrap();
-            Ok(())
-          })
-          .unwrap();
-      });
-      Ok(())
+      this
+        .ui
+        .vertical(|ui| scoped_function_call!(lua, ui, func))
+        .inner
+        .context(in_callback("vertical"))
     });
 
     methods.add_method_mut(
       "horizontal_wrapped",
       |lua, this: &mut LuaUi, func: mlua::Function| {
-        this.ui.horizontal_wrapped(|ui| {
-          lua
-            .scope(|scope| {
-              let lua_ui = scope.create_userdata(LuaUi { ui }).unwrap();
-              let temp_func = scope
-                .create_function(move |_lua, ()| func.call::<()>(lua_ui.clone()))
-                .unwrap();
-              temp_func.call::<()>(()).unwrap();
-              Ok(())
-            })
-            .unwrap();
-        });
-        Ok(())
+        this
+          .ui
+          .horizontal_wrapped(|ui| scoped_function_call!(lua, ui, func))
+          .inner
+          .context(in_callback("horizontal_wrapped"))
       },
     );
 
     methods.add_method_mut(
       "vertical_centered",
       |lua, this: &mut LuaUi, func: mlua::Function| {
-        this.ui.vertical_centered(|ui| {
-          lua
-            .scope(|scope| {
-              let lua_ui = scope.create_userdata(LuaUi { ui }).unwrap();
-              let temp_func = scope
-                .create_function(move |_lua, ()| func.call::<()>(lua_ui.clone()))
-                .unwrap();
-              temp_func.call::<()>(()).unwrap();
-              Ok(())
-            })
-            .unwrap();
-        });
-        Ok(())
+        this
+          .ui
+          .vertical_centered(|ui| scoped_function_call!(lua, ui, func))
+          .inner
+          .context(in_callback("vertical_centered"))
       },
     );
 
     methods.add_method_mut(
       "vertical_centered_justified",
       |lua, this: &mut LuaUi, func: mlua::Function| {
-        this.ui.vertical_centered_justified(|ui| {
-          lua
-            .scope(|scope| {
-              let lua_ui = scope.create_userdata(LuaUi { ui }).unwrap();
-              let temp_func = scope
-                .create_function(move |_lua, ()| func.call::<()>(lua_ui.clone()))
-                .unwrap();
-              temp_func.call::<()>(()).unwrap();
-              Ok(())
-            })
-            .unwrap();
-        });
-        Ok(())
+        this
+          .ui
+          .vertical_centered_justified(|ui| scoped_function_call!(lua, ui, func))
+          .inner
+          .context(in_callback("vertical_centered_justified"))
       },
     );
 
     methods.add_method_mut("group", |lua, this: &mut LuaUi, func: mlua::Function| {
-      this.ui.group(|ui| {
-        lua
-          .scope(|scope| {
-            let lua_ui = scope.create_userdata(LuaUi { ui }).unwrap();
-            let temp_func = scope
-              .create_function(move |_lua, ()| func.call::<()>(lua_ui.clone()))
-              .unwrap();
-            temp_func.call::<()>(()).unwrap();
-            Ok(())
-          })
-          .unwrap();
-      });
-      Ok(())
+      this
+        .ui
+        .group(|ui| scoped_function_call!(lua, ui, func))
+        .inner
+        .context(in_callback("group"))
     });
 
     methods.add_method_mut(
@@ -944,22 +1898,10 @@ impl<'ui> UserData for LuaUi<'ui> {
           }
         }
 
-        scroll_area.show(this.ui, |ui| {
-          lua
-            .scope(|scope| {
-              let lua_ui = scope.create_userdata(LuaUi { ui }).unwrap();
-
-              let temp_func = scope
-                .create_function(move |_lua, ()| func.call::<()>(lua_ui.clone()))
-                .unwrap();
-              temp_func.call::<()>(()).unwrap();
-
-              Ok(())
-            })
-            .unwrap();
-        });
-
-        Ok(())
+        scroll_area
+          .show(this.ui, |ui| scoped_function_call!(lua, ui, func))
+          .inner
+          .context(in_callback("scroll_area"))
       },
     );
 
@@ -974,68 +1916,376 @@ impl<'ui> UserData for LuaUi<'ui> {
     });
 
     methods.add_method_mut("scope", |lua, this: &mut LuaUi, func: mlua::Function| {
-      this.ui.scope(|ui| {
-        lua
-          .scope(|scope| {
-            let lua_ui = scope.create_userdata(LuaUi { ui }).unwrap();
-            let temp_func = scope
-              .create_function(move |_lua, ()| func.call::<()>(lua_ui.clone()))
-              .unwrap();
-            temp_func.call::<()>(()).unwrap();
-            Ok(())
-          })
-          .unwrap();
-      });
-      Ok(())
+      this
+        .ui
+        .scope(|ui| scoped_function_call!(lua, ui, func))
+        .inner
+        .context(in_callback("scope"))
+    });
+
+    // Shows `func` below the widget of `response` while the popup `id` is
+    // open. `open` opens or closes it, and when it is nil clicking the
+    // widget toggles it. Returns whether the popup is open.
+    methods.add_method_mut(
+      "popup_below_widget",
+      |lua,
+       this: &mut LuaUi,
+       (id, response, open, func): (
+        String,
+        mlua::UserDataRef<LuaUiResponse>,
+        Option<bool>,
+        mlua::Function,
+      )| {
+        let popup_id = egui::Id::new(("lulu_popup", id));
+        let is_open = this.ui.memory(|mem| mem.is_popup_open(popup_id));
+        match open {
+          Some(true) if !is_open => this.ui.memory_mut(|mem| mem.open_popup(popup_id)),
+          Some(false) if is_open => this.ui.memory_mut(|mem| mem.close_popup()),
+          None if response.res.clicked() => this.ui.memory_mut(|mem| mem.toggle_popup(popup_id)),
+          _ => {}
+        }
+
+        egui::popup_below_widget(this.ui, popup_id, &response.res, |ui| {
+          scoped_function_call!(lua, ui, func)
+        })
+        .unwrap_or(Ok(()))
+        .context(in_callback("popup_below_widget"))?;
+        Ok(this.ui.memory(|mem| mem.is_popup_open(popup_id)))
+      },
+    );
+
+    // Panels are laid out inside this ui, which must come before the
+    // `central_panel` that takes the space they leave. Their size is kept in
+    // egui's memory under `id`, which eframe saves across runs.
+    methods.add_method_mut(
+      "side_panel",
+      |lua, this: &mut LuaUi, (id, options, func): (String, Option<mlua::Table>, mlua::Function)| {
+        let options = match options {
+          Some(options) => options,
+          None => lua.create_table()?,
+        };
+        let id = egui::Id::new(("lulu_panel", id));
+        let mut panel = match options.get::<Option<String>>("side")?.as_deref() {
+          None | Some("left") => egui::SidePanel::left(id),
+          Some("right") => egui::SidePanel::right(id),
+          Some(other) => {
+            return Err(mlua::Error::runtime(format!("unknown side '{}'", other)))
+              .context(bad_arg("side_panel", "side"));
+          }
+        };
+        if let Some(resizable) = options.get::<Option<bool>>("resizable")? {
+          panel = panel.resizable(resizable);
+        }
+        if let Some(line) = options.get::<Option<bool>>("show_separator_line")? {
+          panel = panel.show_separator_line(line);
+        }
+        if let Some(width) = options.get::<Option<f32>>("default_width")? {
+          panel = panel.default_width(width);
+        }
+        if let Some(width) = options.get::<Option<f32>>("min_width")? {
+          panel = panel.min_width(width);
+        }
+        if let Some(width) = options.get::<Option<f32>>("max_width")? {
+          panel = panel.max_width(width);
+        }
+        if let Some(width) = options.get::<Option<f32>>("exact_width")? {
+          panel = panel.exact_width(width);
+        }
+        let open = options.get::<Option<bool>>("open")?.unwrap_or(true);
+
+        panel
+          .show_animated_inside(this.ui, open, |ui| scoped_function_call!(lua, ui, func))
+          .map(|res| res.inner)
+          .unwrap_or(Ok(()))
+          .context(in_callback("side_panel"))
+      },
+    );
+
+    methods.add_method_mut(
+      "top_bottom_panel",
+      |lua, this: &mut LuaUi, (id, options, func): (String, Option<mlua::Table>, mlua::Function)| {
+        let options = match options {
+          Some(options) => options,
+          None => lua.create_table()?,
+        };
+        let id = egui::Id::new(("lulu_panel", id));
+        let mut panel = match options.get::<Option<String>>("side")?.as_deref() {
+          None | Some("top") => egui::TopBottomPanel::top(id),
+          Some("bottom") => egui::TopBottomPanel::bottom(id),
+          Some(other) => {
+            return Err(mlua::Error::runtime(format!("unknown side '{}'", other)))
+              .context(bad_arg("top_bottom_panel", "side"));
+          }
+        };
+        if let Some(resizable) = options.get::<Option<bool>>("resizable")? {
+          panel = panel.resizable(resizable);
+        }
+        if let Some(line) = options.get::<Option<bool>>("show_separator_line")? {
+          panel = panel.show_separator_line(line);
+        }
+        if let Some(height) = options.get::<Option<f32>>("default_height")? {
+          panel = panel.default_height(height);
+        }
+        if let Some(height) = options.get::<Option<f32>>("min_height")? {
+          panel = panel.min_height(height);
+        }
+        if let Some(height) = options.get::<Option<f32>>("max_height")? {
+          panel = panel.max_height(height);
+        }
+        if let Some(height) = options.get::<Option<f32>>("exact_height")? {
+          panel = panel.exact_height(height);
+        }
+        let open = options.get::<Option<bool>>("open")?.unwrap_or(true);
+
+        panel
+          .show_animated_inside(this.ui, open, |ui| scoped_function_call!(lua, ui, func))
+          .map(|res| res.inner)
+          .unwrap_or(Ok(()))
+          .context(in_callback("top_bottom_panel"))
+      },
+    );
+
+    methods.add_method_mut("central_panel", |lua, this: &mut LuaUi, func: mlua::Function| {
+      egui::CentralPanel::default()
+        .show_inside(this.ui, |ui| scoped_function_call!(lua, ui, func))
+        .inner
+        .context(in_callback("central_panel"))
     });
 
-    // methods.add_method_mut(
-    //   "popup_below_widget",
-    //   |lua, this: &mut LuaUi, (id, func): (String, mlua::Function)| {
-    //     egui::popup_below_widget(
-    //       this.ui,
-    //       egui::Id::new(id),
-    //       &this.ui.available_rect_before_wrap(),
-    //       |ui| {
-    //         lua
-    //           .scope(|scope| {
-    //             let lua_ui = scope.create_userdata(LuaUi { ui }).unwrap();
-    //             let temp_func = scope
-    //               .create_function(move |_lua, ()| func.call::<()>(lua_ui.clone()))
-    //               .unwrap();
-    //             temp_func.call::<()>(()).unwrap();
-    //             Ok(())
-    //           })
-    //           .unwrap();
-    //       },
-    //     );
-    //     Ok(())
-    //   },
-    // );
+    methods.add_method_mut(
+      "tab_bar",
+      |lua, this: &mut LuaUi, (id, tabs, options): (String, mlua::Table, Option<mlua::Table>)| {
+        let id = egui::Id::new(("lulu_tabs", id));
+        show_tab_bar(lua, this.ui, id, tabs, options)
+      },
+    );
+
+    // Fills the rest of the ui with `func`, and takes tabs dragged out of a
+    // `ui:tab_bar` over it. Returns `{ key, zone }` when one is dropped, where
+    // `zone` is "center" or the edge ("left", "right", "top", "bottom") to
+    // split off.
+    methods.add_method_mut(
+      "dock_target",
+      |lua, this: &mut LuaUi, (id, func): (String, mlua::Function)| {
+        let rect = this.ui.available_rect_before_wrap();
+        this
+          .ui
+          .allocate_ui_at_rect(rect, |ui| scoped_function_call!(lua, ui, func))
+          .inner
+          .context(in_callback("dock_target"))?;
+        this.ui.allocate_rect(rect, Sense::hover());
+
+        let ctx = this.ui.ctx().clone();
+        let Some(pointer) = ctx.pointer_hover_pos().filter(|p| rect.contains(*p)) else {
+          return Ok(mlua::Value::Nil);
+        };
+        if !egui::DragAndDrop::has_payload_of_type::<TabDrag>(&ctx) {
+          return Ok(mlua::Value::Nil);
+        }
+
+        let (zone, preview) = dock_zone(rect, pointer);
+        let layer = LayerId::new(egui::Order::Foreground, egui::Id::new(("lulu_dock", id)));
+        ctx.layer_painter(layer).rect_filled(
+          preview,
+          4.0,
+          this.ui.visuals().selection.bg_fill.linear_multiply(0.4),
+        );
+
+        // Tab bars inside `func` ran first, so a tab dropped on one of them
+        // has already been taken and only drops elsewhere in `rect` are left.
+        if !ctx.input(|i| i.pointer.primary_released()) {
+          return Ok(mlua::Value::Nil);
+        }
+        let Some(payload) = egui::DragAndDrop::take_payload::<TabDrag>(&ctx) else {
+          return Ok(mlua::Value::Nil);
+        };
+        let dropped = lua.create_table()?;
+        dropped.set("key", payload.0.clone())?;
+        dropped.set("zone", zone)?;
+        Ok(mlua::Value::Table(dropped))
+      },
+    );
+
+    // Splits the rest of the ui in two, side by side or stacked when
+    // `vertical`, with `fraction` of it for `first`. Returns the fraction,
+    // and whether dragging the line between them changed it this frame.
+    methods.add_method_mut(
+      "split",
+      |lua,
+       this: &mut LuaUi,
+       (id, vertical, fraction, first, second): (
+        String,
+        bool,
+        f32,
+        mlua::Function,
+        mlua::Function,
+      )| {
+        let rect = this.ui.available_rect_before_wrap();
+        let spacing = this.ui.spacing().item_spacing;
+        let gap = if vertical { spacing.y } else { spacing.x }.max(4.0);
+        let (start, length) = if vertical {
+          (rect.top(), rect.height())
+        } else {
+          (rect.left(), rect.width())
+        };
+        let at = |fraction: f32| start + (length - gap) * fraction;
+        let handle_rect = |at: f32| {
+          if vertical {
+            Rect::from_x_y_ranges(rect.x_range(), at..=at + gap)
+          } else {
+            Rect::from_x_y_ranges(at..=at + gap, rect.y_range())
+          }
+        };
+
+        let mut fraction = fraction.clamp(0.05, 0.95);
+        let mut changed = false;
+        let handle = this.ui.interact(
+          handle_rect(at(fraction)),
+          egui::Id::new(("lulu_split", id)),
+          Sense::drag(),
+        );
+        if handle.dragged() {
+          let delta = if vertical { handle.drag_delta().y } else { handle.drag_delta().x };
+          let dragged = (fraction + delta / (length - gap).max(1.0)).clamp(0.05, 0.95);
+          changed = dragged != fraction;
+          fraction = dragged;
+        }
+        if handle.hovered() || handle.dragged() {
+          this.ui.ctx().set_cursor_icon(if vertical {
+            CursorIcon::ResizeVertical
+          } else {
+            CursorIcon::ResizeHorizontal
+          });
+        }
+
+        let split = at(fraction);
+        let handle_rect = handle_rect(split);
+        let (first_rect, second_rect) = if vertical {
+          (
+            Rect::from_x_y_ranges(rect.x_range(), rect.top()..=split),
+            Rect::from_x_y_ranges(rect.x_range(), handle_rect.bottom()..=rect.bottom()),
+          )
+        } else {
+          (
+            Rect::from_x_y_ranges(rect.left()..=split, rect.y_range()),
+            Rect::from_x_y_ranges(handle_rect.right()..=rect.right(), rect.y_range()),
+          )
+        };
+
+        let stroke = if handle.hovered() || handle.dragged() {
+          this.ui.visuals().widgets.active.bg_stroke
+        } else {
+          this.ui.visuals().widgets.noninteractive.bg_stroke
+        };
+        if vertical {
+          this.ui.painter().hline(rect.x_range(), handle_rect.center().y, stroke);
+        } else {
+          this.ui.painter().vline(handle_rect.center().x, rect.y_range(), stroke);
+        }
+
+        for (rect, func) in [(first_rect, first), (second_rect, second)] {
+          this
+            .ui
+            .allocate_ui_at_rect(rect, |ui| {
+              ui.set_clip_rect(rect.intersect(ui.clip_rect()));
+              scoped_function_call!(lua, ui, func)
+            })
+            .inner
+            .context(in_callback("split"))?;
+        }
+        this.ui.allocate_rect(rect, Sense::hover());
+        Ok((fraction, changed))
+      },
+    );
 
     methods.add_method_mut(
       "window",
       |lua, this: &mut LuaUi, (title, func): (String, mlua::Function)| {
-        egui::Window::new(title).show(this.ui.ctx(), |ui| {
-          lua
-            .scope(|scope| {
-              let lua_ui = scope.create_userdata(LuaUi { ui }).unwrap();
-              let temp_func = scope
-                .create_function(move |_lua, ()| func.call::<()>(lua_ui.clone()))
-                .unwrap();
-              temp_func.call::<()>(()).unwrap();
-              Ok(())
-            })
-            .unwrap();
+        egui::Window::new(title)
+          .show(this.ui.ctx(), |ui| scoped_function_call!(lua, ui, func))
+          .and_then(|res| res.inner)
+          .unwrap_or(Ok(()))
+          .context(in_callback("window"))
+      },
+    );
+
+    methods.add_method_mut(
+      "viewport",
+      |lua, this: &mut LuaUi, (id, options, func): (String, Option<mlua::Table>, mlua::Function)| {
+        let ctx = this.ui.ctx().clone();
+        show_viewport(lua, &ctx, id, options, func)
+      },
+    );
+
+    // Shows `func` centered above a backdrop that dims the rest of the UI and
+    // takes its clicks. Keyboard input only reaches the topmost modal. `dim`
+    // is the backdrop's opacity, from 0 to 1. Returns whether the backdrop
+    // was clicked and whether Escape was pressed in this modal.
+    methods.add_method_mut(
+      "modal",
+      |lua, this: &mut LuaUi, (id, options, func): (String, Option<mlua::Table>, mlua::Function)| {
+        let dim = match &options {
+          Some(options) => options.get::<Option<f32>>("dim")?.unwrap_or(0.5),
+          None => 0.5,
+        };
+        let ctx = this.ui.ctx().clone();
+        let id = egui::Id::new(("lulu_modal", id));
+        let screen = ctx.screen_rect();
+
+        let backdrop = egui::Area::new(id.with("backdrop"))
+          .order(egui::Order::Foreground)
+          .fixed_pos(screen.min)
+          .constrain(false)
+          .show(&ctx, |ui| {
+            ui.painter().rect_filled(
+              screen,
+              0.0,
+              Color32::from_black_alpha((dim.clamp(0.0, 1.0) * 255.0) as u8),
+            );
+            ui.allocate_rect(screen, Sense::click())
+          });
+        // Keeps a modal opened from another one above it.
+        ctx.move_to_top(backdrop.response.layer_id);
+
+        // Only the topmost modal of the last frame gets the keyboard input
+        // held back by `hold_modal_input`, and only while its content runs.
+        let (held, events) = ctx.data_mut(|d| {
+          let stack = d.get_temp_mut_or_default::<ModalStack>(egui::Id::new(MODAL_STACK));
+          stack.shown.push(id);
+          let events = if stack.top == Some(id) {
+            std::mem::take(&mut stack.events)
+          } else {
+            Vec::new()
+          };
+          (stack.top.is_some(), events)
         });
-        Ok(())
+        let escape = events.iter().any(|event| {
+          matches!(event, egui::Event::Key { key: Key::Escape, pressed: true, .. })
+        });
+        ctx.input_mut(|i| i.events.extend(events));
+
+        let content = egui::Area::new(id)
+          .order(egui::Order::Foreground)
+          .anchor(Align2::CENTER_CENTER, Vec2::ZERO)
+          .show(&ctx, |ui| {
+            Frame::window(ui.style())
+              .show(ui, |ui| scoped_function_call!(lua, ui, func))
+              .inner
+          });
+        ctx.move_to_top(content.response.layer_id);
+        if held {
+          ctx.input_mut(|i| i.events.retain(|event| !is_keyboard_event(event)));
+        }
+        content.inner.context(in_callback("modal"))?;
+
+        Ok((backdrop.inner.clicked(), escape))
       },
     );
 
     methods.add_method_mut(
       "color_picker",
       |_lua, this: &mut LuaUi, color_table: mlua::Table| {
-        let mut color = color_from_lua_table(color_table).unwrap();
+        let mut color = lua_color(color_table).context(bad_arg("color_picker", "color"))?;
         let response = egui::widgets::color_picker::color_edit_button_srgba(
           this.ui,
           &mut color,
@@ -1065,66 +2315,52 @@ impl<'ui> UserData for LuaUi<'ui> {
     methods.add_method_mut(
       "grid",
       |lua, this: &mut LuaUi, (id, func): (String, mlua::Function)| {
-        egui::Grid::new(id).show(this.ui, |ui| {
-          lua
-            .scope(|scope| {
-              let lua_ui = scope.create_userdata(LuaUi { ui }).unwrap();
-              let temp_func = scope
-                .create_function(move |_lua, ()| func.call::<()>(lua_ui.clone()))
-                .unwrap();
-              temp_func.call::<()>(()).unwrap();
-              Ok(())
-            })
-            .unwrap();
-        });
-        Ok(())
+        egui::Grid::new(id)
+          .show(this.ui, |ui| scoped_function_call!(lua, ui, func))
+          .inner
+          .context(in_callback("grid"))
       },
     );
 
     methods.add_method_mut(
       "collapsing_header",
       |lua, this: &mut LuaUi, (label, func): (String, mlua::Function)| {
-        this.ui.collapsing(label, |ui| {
-          lua
-            .scope(|scope| {
-              let lua_ui = scope.create_userdata(LuaUi { ui }).unwrap();
-              let temp_func = scope
-                .create_function(move |_lua, ()| func.call::<()>(lua_ui.clone()))
-                .unwrap();
-              temp_func.call::<()>(()).unwrap();
-              Ok(())
-            })
-            .unwrap();
-        });
-        Ok(())
+        this
+          .ui
+          .collapsing(label, |ui| scoped_function_call!(lua, ui, func))
+          .body_returned
+          .unwrap_or(Ok(()))
+          .context(in_callback("collapsing_header"))
+      },
+    );
+
+    methods.add_method_mut(
+      "table",
+      |lua, this: &mut LuaUi, (id, options, cell): (Option<String>, mlua::Table, mlua::Function)| {
+        let id = match id {
+          Some(id) => egui::Id::new(("lulu_table", id)),
+          None => this.ui.next_auto_id(),
+        };
+        this
+          .ui
+          .push_id(id, |ui| show_table(lua, ui, options, cell))
+          .inner
       },
     );
 
-    // methods.add_method_mut("table", |lua, this: &mut LuaUi, (id, func): (String, LuaFunction)| {
-    //   let mut table = egui_extras::TableBuilder::new(this.ui)
-    //     .striped(true)
-    //     .resizable(true)
-    //     .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
-    //     .column(egui_extras::Column::auto().resizable(true));
-
-    //   table.body(|body| {
-    //     body.rows(18.0, 1, |mut row| {
-    //       row.col(|ui| {
-    //         lua
-    //           .scope(|scope| {
-    //             let lua_ui = scope.create_userdata(LuaUi { ui }).unwrap();
-    //             let temp_func = scope
-    //               .create_function(move |_lua, ()| func.call::<()>(lua_ui.clone()))
-    //               .unwrap();
-    //             temp_func.call::<()>(()).unwrap();
-    //             Ok(())
-    //           })
-    //           .unwrap();
-    //       });
-    //     });
-    //   });
-    //   Ok(())
-    // });
+    methods.add_method_mut(
+      "virtual_list",
+      |lua, this: &mut LuaUi, (id, options, render): (Option<String>, mlua::Table, mlua::Function)| {
+        let id = match id {
+          Some(id) => egui::Id::new(("lulu_virtual_list", id)),
+          None => this.ui.next_auto_id(),
+        };
+        this
+          .ui
+          .push_id(id, |ui| show_virtual_list(lua, ui, options, render))
+          .inner
+      },
+    );
 
     methods.add_method_mut(
       "set_attribs",
@@ -1155,9 +2391,9 @@ impl<'ui> UserData for LuaUi<'ui> {
         }
 
         set_attrib!(
-          ("spacing", mlua::Table),
+          try ("spacing", mlua::Table) in "set_style",
           style_table,
-          |style_table: mlua::Table| {
+          |style_table: mlua::Table| -> mlua::Result<()> {
             set_attrib!(
               ("item_spacing", mlua::Table),
               style_table,
@@ -1186,9 +2422,12 @@ impl<'ui> UserData for LuaUi<'ui> {
             );
 
             set_attrib!(
-              ("menu_margin", mlua::Value),
+              try ("menu_margin", mlua::Value),
               style_table,
-              |val: mlua::Value| { style.spacing.menu_margin = table_into_margin!(val) }
+              |val: mlua::Value| -> mlua::Result<()> {
+                style.spacing.menu_margin = table_into_margin(val)?;
+                Ok(())
+              }
             );
 
             set_attrib!(("indent", f32), style_table, |val: f32| style
@@ -1234,6 +2473,7 @@ impl<'ui> UserData for LuaUi<'ui> {
               .spacing
               .combo_height =
               val);
+            Ok(())
           }
         );
 
@@ -1289,9 +2529,9 @@ impl<'ui> UserData for LuaUi<'ui> {
         );
 
         set_attrib!(
-          ("visuals", mlua::Table),
+          try ("visuals", mlua::Table) in "set_style",
           style_table,
-          |style_table: mlua::Table| {
+          |style_table: mlua::Table| -> mlua::Result<()> {
             set_attrib!(("dark_mode", bool), style_table, |val: bool| style
               .visuals
               .dark_mode =
@@ -1340,9 +2580,12 @@ impl<'ui> UserData for LuaUi<'ui> {
             );
 
             set_attrib!(
-              ("window_rounding", mlua::Value),
+              try ("window_rounding", mlua::Value),
               style_table,
-              |val: mlua::Value| style.visuals.window_rounding = table_into_rounding!(val)
+              |val: mlua::Value| -> mlua::Result<()> {
+                style.visuals.window_rounding = table_into_rounding(val)?;
+                Ok(())
+              }
             );
 
             set_attrib!(
@@ -1362,6 +2605,7 @@ impl<'ui> UserData for LuaUi<'ui> {
                 style.visuals.override_text_color = Some(color);
               }
             }
+            Ok(())
           }
         );
 
@@ -1387,22 +2631,18 @@ impl<'ui> UserData for LuaUi<'ui> {
     methods.add_method_mut(
       "columns",
       |lua, this: &mut LuaUi, (n, func): (usize, mlua::Function)| {
-        this.ui.columns(n, |columns| {
-          for (i, column) in columns.iter_mut().enumerate() {
-            let func = func.clone(); // clone here
-            lua
-              .scope(|scope| {
-                let lua_ui = scope.create_userdata(LuaUi { ui: column }).unwrap();
-                let temp_func = scope
-                  .create_function(move |_lua, ()| func.call::<()>((i + 1, lua_ui.clone())))
-                  .unwrap();
-                temp_func.call::<()>(()).unwrap();
-                Ok(())
-              })
-              .unwrap();
-          }
-        });
-        Ok(())
+        this
+          .ui
+          .columns(n, |columns| {
+            for (i, column) in columns.iter_mut().enumerate() {
+              lua.scope(|scope| {
+                let lua_ui = scope.create_userdata(LuaUi { ui: column })?;
+                func.call::<()>((i + 1, lua_ui))
+              })?;
+            }
+            Ok(())
+          })
+          .context(in_callback("columns"))
       },
     );
 
@@ -1420,18 +2660,10 @@ impl<'ui> UserData for LuaUi<'ui> {
         this
           .ui
           .allocate_ui_with_layout(egui::Vec2::INFINITY, layout, |ui| {
-            lua
-              .scope(|scope| {
-                let lua_ui = scope.create_userdata(LuaUi { ui }).unwrap();
-                let temp_func = scope
-                  .create_function(move |_lua, ()| func.call::<()>(lua_ui.clone()))
-                  .unwrap();
-                temp_func.call::<()>(()).unwrap();
-                Ok(())
-              })
-              .unwrap();
-          });
-        Ok(())
+            scoped_function_call!(lua, ui, func)
+          })
+          .inner
+          .context(in_callback("allocate_ui_with_layout"))
       },
     );
 
@@ -1448,31 +2680,75 @@ impl<'ui> UserData for LuaUi<'ui> {
     methods.add_method_mut(
       "menu_button",
       |lua, this: &mut LuaUi, (label, func): (String, mlua::Function)| {
-        this.ui.menu_button(label, |ui| {
-          lua
-            .scope(|scope| {
-              let lua_ui = scope.create_userdata(LuaUi { ui }).unwrap();
-              let temp_func = scope
-                .create_function(move |_lua, ()| func.call::<()>(lua_ui.clone()))
-                .unwrap();
-              temp_func.call::<()>(()).unwrap();
-              Ok(())
-            })
-            .unwrap();
-        });
-        Ok(())
+        this
+          .ui
+          .menu_button(label, |ui| scoped_function_call!(lua, ui, func))
+          .inner
+          .unwrap_or(Ok(()))
+          .context(in_callback("menu_button"))
       },
     );
 
+    methods.add_method_mut("close_menu", |_, this: &mut LuaUi, ()| {
+      this.ui.close_menu();
+      Ok(())
+    });
+
+    methods.add_method_mut("menu_bar", |lua, this: &mut LuaUi, func: mlua::Function| {
+      egui::menu::bar(this.ui, |ui| scoped_function_call!(lua, ui, func))
+        .inner
+        .context(in_callback("menu_bar"))
+    });
+
+    // A menu button showing its `shortcut` on the right, greyed out when
+    // `enabled` is false and with a check mark when `checked` is set.
+    methods.add_method_mut(
+      "menu_item",
+      |_, this: &mut LuaUi, (text, options): (String, Option<mlua::Table>)| {
+        let mut shortcut = None;
+        let mut enabled = true;
+        let mut checked = None;
+        if let Some(options) = options {
+          shortcut = options.get::<Option<String>>("shortcut")?;
+          enabled = options.get::<Option<bool>>("enabled")?.unwrap_or(true);
+          checked = options.get::<Option<bool>>("checked")?;
+        }
+
+        let text = match checked {
+          Some(true) => format!("✔ {}", text),
+          Some(false) => format!("  {}", text),
+          None => text,
+        };
+        let mut button = Button::new(text);
+        if let Some(shortcut) = shortcut {
+          let shortcut = lua_shortcut(&shortcut).context(bad_arg("menu_item", "shortcut"))?;
+          button = button.shortcut_text(this.ui.ctx().format_shortcut(&shortcut));
+        }
+
+        Ok(LuaUiResponse {
+          res: this.ui.add_enabled(enabled, button),
+          value: None,
+        })
+      },
+    );
+
+    // Whether `shortcut` was pressed this frame, taking the key press so no
+    // widget after this sees it.
+    methods.add_method("consume_shortcut", |_, this: &LuaUi, shortcut: String| {
+      let shortcut = lua_shortcut(&shortcut).context(bad_arg("consume_shortcut", "shortcut"))?;
+      Ok(this.ui.ctx().input_mut(|i| i.consume_shortcut(&shortcut)))
+    });
+
     methods.add_method_mut(
       "frame_block",
       |lua, this: &mut LuaUi, (style, func): (mlua::Table, mlua::Function)| {
         let mut frame = Frame::none();
 
-        stylize_element!(frame, style);
+        stylize_element!(frame, style, "frame_block");
 
         if let Ok(padding) = style.get::<mlua::Value>("padding") {
-          frame = frame.inner_margin(table_into_margin!(padding));
+          frame =
+            frame.inner_margin(table_into_margin(padding).context(bad_arg("frame_block", "padding"))?);
         }
 
         let min_size = if let Ok(size_tbl) = style.get::<mlua::Table>("min_size") {
@@ -1504,19 +2780,10 @@ impl<'ui> UserData for LuaUi<'ui> {
             }
           }
 
-          lua
-            .scope(|scope| {
-              let lua_ui = scope.create_userdata(LuaUi { ui }).unwrap();
-              let temp_func = scope
-                .create_function(move |_lua, ()| func.call::<()>(lua_ui.clone()))
-                .unwrap();
-              temp_func.call::<()>(()).unwrap();
-              Ok(())
-            })
-            .unwrap();
-        });
-
-        Ok(())
+          scoped_function_call!(lua, ui, func)
+        })
+        .inner
+        .context(in_callback("frame_block"))
       },
     );
 
@@ -1532,45 +2799,58 @@ impl<'ui> UserData for LuaUi<'ui> {
           _ => Layout::default(),
         };
         if layout_name.as_str() == "center_both" {
-          this.ui.centered_and_justified(|ui| {
-            scoped_function_call!(lua, ui, func);
-          });
+          this
+            .ui
+            .centered_and_justified(|ui| scoped_function_call!(lua, ui, func))
+            .inner
         } else {
-          this.ui.with_layout(layout, |ui| {
-            scoped_function_call!(lua, ui, func);
-          });
+          this
+            .ui
+            .with_layout(layout, |ui| scoped_function_call!(lua, ui, func))
+            .inner
         }
-        Ok(())
+        .context(in_callback("align"))
       },
     );
 
-    // context_menu
-    // methods.add_method_mut("context_menu", |lua, this: &mut LuaUi, func: mlua::Function| {
-    //   this.ui.context_menu(|ui| {
-    //     let lua_ui = LuaUi { ui };
-    //     func.call::<()>(lua_ui).unwrap();
-    //   });
-    //   Ok(())
-    // });
 
     methods.add_method_mut(
       "place_ui_at",
       |lua, this: &mut LuaUi, (x, y, w, h, func): (f32, f32, f32, f32, mlua::Function)| {
         let rect = Rect::from_min_size(egui::pos2(x, y), Vec2::new(w, h));
-        this.ui.allocate_ui(rect.size(), |ui| {
-          ui.set_clip_rect(rect); // optional
-          lua
-            .scope(|scope| {
-              let lua_ui = scope.create_userdata(LuaUi { ui }).unwrap();
-              let temp_func = scope
-                .create_function(move |_lua, ()| func.call::<()>(lua_ui.clone()))
-                .unwrap();
-              temp_func.call::<()>(()).unwrap();
-              Ok(())
-            })
-            .unwrap();
-        });
-        Ok(())
+        this
+          .ui
+          .allocate_ui(rect.size(), |ui| {
+            ui.set_clip_rect(rect); // optional
+            scoped_function_call!(lua, ui, func)
+          })
+          .inner
+          .context(in_callback("place_ui_at"))
+      },
+    );
+
+    // Renders `func` like a scope, then keeps what it drew only if `keep()`
+    // returns true; otherwise it is erased and takes no space.
+    methods.add_method_mut(
+      "tentative",
+      |lua, this: &mut LuaUi, (func, keep): (mlua::Function, mlua::Function)| {
+        let painter = this.ui.painter().clone();
+        let first = painter.add(Shape::Noop);
+        let mut child = this
+          .ui
+          .child_ui(this.ui.available_rect_before_wrap(), *this.ui.layout());
+        scoped_function_call!(lua, &mut child, func).context(in_callback("tentative"))?;
+        let kept = keep.call::<bool>(()).context(in_callback("tentative"))?;
+        let last = painter.add(Shape::Noop);
+
+        if kept {
+          this.ui.allocate_rect(child.min_rect(), Sense::hover());
+        } else {
+          for idx in first.0 + 1..last.0 {
+            painter.set(layers::ShapeIdx(idx), Shape::Noop);
+          }
+        }
+        Ok(kept)
       },
     );
 
@@ -1585,27 +2865,21 @@ impl<'ui> UserData for LuaUi<'ui> {
     });
 
     methods.add_method("keydown", |_, this: &LuaUi, key: String| {
-      use egui::Key;
-
-      let key = Key::from_name(&key).unwrap();
+      let key = lua_key(&key).context(bad_arg("keydown", "key"))?;
 
       let down = this.ui.ctx().input(|i| i.key_down(key));
       Ok(down)
     });
 
     methods.add_method("keypressed", |_, this: &LuaUi, key: String| {
-      use egui::Key;
-
-      let key = Key::from_name(&key).unwrap();
+      let key = lua_key(&key).context(bad_arg("keypressed", "key"))?;
 
       let pressed = this.ui.ctx().input(|i| i.key_pressed(key));
       Ok(pressed)
     });
 
     methods.add_method("keyup", |_, this: &LuaUi, key: String| {
-      use egui::Key;
-
-      let key = Key::from_name(&key).unwrap();
+      let key = lua_key(&key).context(bad_arg("keyup", "key"))?;
 
       let released = this.ui.ctx().input(|i| i.key_released(key));
       Ok(released)
@@ -1624,7 +2898,7 @@ impl UserData for LuaPainter {
       "rect_filled",
       |_, this: &mut LuaPainter, (x, y, w, h, color): (f32, f32, f32, f32, mlua::Table)| {
         let rect = Rect::from_min_size(egui::pos2(x, y), Vec2::new(w, h));
-        let color = color_from_lua_table(color).unwrap();
+        let color = lua_color(color).context("painter:rect_filled: invalid `color`")?;
         this.painter.rect_filled(rect, 0.0, color);
         Ok(())
       },
@@ -1634,7 +2908,7 @@ impl UserData for LuaPainter {
       "circle_filled",
       |_, this: &mut LuaPainter, (x, y, radius, color): (f32, f32, f32, mlua::Table)| {
         let center = egui::pos2(x, y);
-        let color = color_from_lua_table(color).unwrap();
+        let color = lua_color(color).context("painter:circle_filled: invalid `color`")?;
         this.painter.circle_filled(center, radius, color);
         Ok(())
       },
@@ -1646,7 +2920,7 @@ impl UserData for LuaPainter {
        this: &mut LuaPainter,
        (x1, y1, x2, y2, color, width): (f32, f32, f32, f32, mlua::Table, f32)| {
         let points = [egui::pos2(x1, y1), egui::pos2(x2, y2)];
-        let color = color_from_lua_table(color).unwrap();
+        let color = lua_color(color).context("painter:line_segment: invalid `color`")?;
         this.painter.line_segment(points, Stroke::new(width, color));
         Ok(())
       },
@@ -1658,7 +2932,7 @@ impl UserData for LuaPainter {
        this: &mut LuaPainter,
        (x, y, radius, color, width): (f32, f32, f32, mlua::Table, f32)| {
         let center = egui::pos2(x, y);
-        let color = color_from_lua_table(color).unwrap();
+        let color = lua_color(color).context("painter:circle_stroke: invalid `color`")?;
         this
           .painter
           .circle_stroke(center, radius, Stroke::new(width, color));
@@ -1672,7 +2946,7 @@ impl UserData for LuaPainter {
        this: &mut LuaPainter,
        (x, y, w, h, color, width): (f32, f32, f32, f32, mlua::Table, f32)| {
         let rect = Rect::from_min_size(egui::pos2(x, y), Vec2::new(w, h));
-        let color = color_from_lua_table(color).unwrap();
+        let color = lua_color(color).context("painter:rect_stroke: invalid `color`")?;
         this
           .painter
           .rect_stroke(rect, 0.0, Stroke::new(width, color));
@@ -1688,7 +2962,7 @@ impl UserData for LuaPainter {
        this: &mut LuaPainter,
        (x, y, text, font_size, color): (f32, f32, String, f32, mlua::Table)| {
         let pos = egui::pos2(x, y);
-        let color = color_from_lua_table(color).unwrap();
+        let color = lua_color(color).context("painter:text: invalid `color`")?;
         this.painter.text(
           pos,
           Align2::LEFT_TOP,
@@ -1707,7 +2981,7 @@ impl UserData for LuaPainter {
        (x, y, dx, dy, color, width): (f32, f32, f32, f32, mlua::Table, f32)| {
         let origin = egui::pos2(x, y);
         let vec = egui::vec2(dx, dy);
-        let color = color_from_lua_table(color).unwrap();
+        let color = lua_color(color).context("painter:arrow: invalid `color`")?;
         this.painter.arrow(origin, vec, Stroke::new(width, color));
         Ok(())
       },
@@ -1726,7 +3000,7 @@ impl UserData for LuaPainter {
     methods.add_method_mut(
       "add_shape",
       |_, this: &mut LuaPainter, shape: mlua::AnyUserData| {
-        let shape = shape.borrow::<LuaShape>().unwrap();
+        let shape = shape.borrow::<LuaShape>()?;
         this.painter.extend(vec![shape.shape.clone()]);
         Ok(())
       },
@@ -1754,7 +3028,7 @@ impl UserData for LuaPainter {
         let mut shapes = Vec::new();
         for shape in tables.sequence_values::<mlua::AnyUserData>() {
           if let Ok(shape) = shape {
-            let shape = shape.borrow::<LuaShape>().unwrap();
+            let shape = shape.borrow::<LuaShape>()?;
             shapes.push(shape.shape.clone())
           }
         }
@@ -1766,14 +3040,15 @@ impl UserData for LuaPainter {
 }
 
 impl LuluUiApp {
-  fn new(
-    _cc: &eframe::CreationContext<'_>,
+  pub fn new(
+    ctx: &egui::Context,
     lulu: Lulu,
     main: Option<mlua::Value>,
     err: Option<String>,
+    sources: Sources,
   ) -> Self {
-    egui_extras::install_image_loaders(&_cc.egui_ctx);
-    lulu.lua.set_app_data(_cc.egui_ctx.clone());
+    egui_extras::install_image_loaders(ctx);
+    lulu.lua.set_app_data(ctx.clone());
 
     lulu
       .lua
@@ -1784,7 +3059,8 @@ impl LuluUiApp {
           .lua
           .create_function(|_, tab: mlua::Table| {
             Ok(LuaShape {
-              shape: from_lua_table(tab).unwrap(),
+              shape: from_lua_table(tab)
+                .ok_or_else(|| mlua::Error::runtime("Shape2D: invalid shape table"))?,
             })
           })
           .unwrap(),
@@ -1824,96 +3100,390 @@ impl LuluUiApp {
       )
       .unwrap();
 
-    let mut init_error = err;
-
-    if let Some(main) = main {
-      match main {
-        mlua::Value::Function(f) => match f.call::<mlua::Value>(()) {
-          Ok(v) => match v {
-            mlua::Value::Function(f) => match lulu.lua.globals().set("ui_update", f) {
-              _ => {}
-            },
-            _ => {}
-          },
-          Err(e) => {
-            init_error = Some(e.to_string());
-          }
-        },
-        _ => {
-          init_error = Some("Expected main to return init function, recieved nothing".to_string());
-        }
+    lulu
+      .lua
+      .globals()
+      .set(
+        "request_exit",
+        lulu
+          .lua
+          .create_function(|lua, code: Option<i32>| {
+            lua.set_app_data(ExitCode(code.unwrap_or(0)));
+            Ok(())
+          })
+          .unwrap(),
+      )
+      .unwrap();
+
+    lulu
+      .lua
+      .globals()
+      .set(
+        "report_error",
+        lulu
+          .lua
+          .create_function(|lua, (kind, message): (String, String)| {
+            let kind = ErrorKind::from_name(&kind)
+              .ok_or_else(|| mlua::Error::runtime(format!("unknown error kind '{}'", kind)))?;
+            if lua.app_data_ref::<ReportedError>().is_none() {
+              lua.set_app_data(ReportedError(kind, message));
+            }
+            Ok(())
+          })
+          .unwrap(),
+      )
+      .unwrap();
+
+    window::register(&lulu.lua).unwrap();
+
+    lulu
+      .lua
+      .globals()
+      .set(
+        "request_repaint",
+        lulu
+          .lua
+          .create_function(|lua, after: Option<f64>| {
+            if let Some(ctx) = lua.app_data_ref::<egui::Context>() {
+              match after {
+                Some(after) if after > 0.0 => {
+                  ctx.request_repaint_after(std::time::Duration::from_secs_f64(after))
+                }
+                _ => ctx.request_repaint(),
+              }
+            }
+            Ok(())
+          })
+          .unwrap(),
+      )
+      .unwrap();
+
+    lulu
+      .lua
+      .globals()
+      .set("ASYNC_POLL_PENDING", mlua::Lua::poll_pending())
+      .unwrap();
+
+    let mut app = Self {
+      error: err.map(|err| AppError::new(ErrorKind::Init, &err, &lulu, &sources)),
+      dev: DevReload::from_args(&lulu.args, &sources),
+      lulu,
+      main,
+      sources,
+      last_frame: Vec::new(),
+    };
+    app.init();
+    app
+  }
+
+  /// Calls the init function returned by main, which gives back `ui_update`.
+  fn init(&mut self) {
+    let Some(main) = &self.main else { return };
+
+    let result = match main {
+      mlua::Value::Function(f) => f.call::<mlua::Value>(()).map_err(|e| e.to_string()),
+      _ => Err("Expected main to return init function, recieved nothing".to_string()),
+    };
+
+    match result {
+      Ok(mlua::Value::Function(f)) => {
+        let _ = self.lulu.lua.globals().set("ui_update", f);
       }
+      Ok(_) => {}
+      Err(err) => self.fail(ErrorKind::Init, &err),
     }
+  }
 
-    Self { lulu, init_error }
+  fn fail(&mut self, kind: ErrorKind, err: &str) {
+    self.error = Some(AppError::new(kind, err, &self.lulu, &self.sources));
   }
-}
 
-impl eframe::App for LuluUiApp {
-  fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-    // Changed frame back to _frame
-    self.lulu.lua.set_app_data(ctx.clone());
-    match self
-      .lulu
-      .lua
-      .globals()
-      .set("cpu_usage", _frame.info().cpu_usage.unwrap_or(0.0))
-    {
+  /// Clears the current error and runs the app again, re-running the init
+  /// function first if that is what failed.
+  fn retry(&mut self) {
+    let kind = self.error.take().map(|err| err.kind);
+    self.lulu.lua.remove_app_data::<ReportedError>();
+    if kind == Some(ErrorKind::Init) {
+      self.init();
+    }
+  }
+
+  /// Recompiles the app from disk and swaps in the new `ui_update`, carrying
+  /// over the `State` of components that are still in the tree.
+  fn reload(&mut self) {
+    let preserve = self.dev.as_ref().is_some_and(|dev| dev.preserve_state);
+    let snapshot = if preserve {
+      self
+        .lulu
+        .lua
+        .load("return ui.snapshot_state()")
+        .eval::<mlua::Value>()
+        .ok()
+    } else {
+      None
+    };
+
+    let result = dev::recompile(&mut self.lulu, &self.sources)
+      .map_err(|e| e.to_string())
+      .and_then(|_| exec_app(&mut self.lulu));
+
+    self.sources.refresh(&self.lulu);
+    if let Some(dev) = &mut self.dev {
+      dev.watch(&self.sources);
+    }
+
+    self.error = None;
+    self.lulu.lua.remove_app_data::<ReportedError>();
+    let _ = self.lulu.lua.globals().set("ui_update", mlua::Value::Nil);
+
+    match result {
+      Ok(main) => {
+        self.main = Some(main);
+        self.init();
+      }
       Err(err) => {
-        self.init_error = Some(err.to_string());
+        self.main = None;
+        self.fail(ErrorKind::Init, &err);
       }
-      Ok(_) => {}
     }
 
-    if let Some(err) = &self.init_error {
-      egui::CentralPanel::default().show(ctx, |ui| {
-        ui.heading("Lua Initialization Error:");
-        ui.label(err.to_string());
-      });
-    } else if let Ok(render_fn) = self.lulu.lua.globals().get::<mlua::Function>("ui_update") {
-      egui::CentralPanel::default().show(ctx, |ui| {
-        match self.lulu.lua.scope(|_scope| {
-          let lua_ui = _scope.create_userdata(LuaUi { ui }).unwrap();
-          let temp_func = _scope
-            .create_function(move |_lua, ()| render_fn.call::<()>(lua_ui.clone()))
-            .unwrap();
-          temp_func.call::<()>(())?;
-          Ok(())
-        }) {
-          Err(err) => {
-            ui.heading("Lua Initialization Error:");
-            ui.label(err.to_string());
-          }
-          Ok(_) => {}
+    if self.error.is_none()
+      && let Some(snapshot) = snapshot
+      && let Err(err) = self
+        .lulu
+        .lua
+        .load("ui.restore_state(...)")
+        .call::<()>(snapshot)
+    {
+      self.fail(ErrorKind::Init, &err.to_string());
+    }
+  }
+
+  /// Runs one frame of the app against `ctx`. This is everything `update`
+  /// does that does not depend on an eframe window.
+  pub fn frame(&mut self, ctx: &egui::Context) {
+    self.lulu.lua.set_app_data(ctx.clone());
+
+    if let Some(dev) = &mut self.dev {
+      if dev.changed() {
+        self.reload();
+      }
+      ctx.request_repaint_after(dev::POLL_INTERVAL);
+    }
+
+    if self.error.is_none() {
+      self.run_timers(ctx);
+    }
+
+    if self.error.is_none()
+      && let Ok(render_fn) = self.lulu.lua.globals().get::<mlua::Function>("ui_update")
+    {
+      hold_modal_input(ctx);
+
+      // An app that lays out its own panels gets a bare root to put them in.
+      let mut shell = egui::CentralPanel::default();
+      if self.declares_panels() {
+        shell = shell.frame(egui::Frame::none());
+      }
+      let result = shell
+        .show(ctx, |ui| scoped_function_call!(self.lulu.lua, ui, render_fn))
+        .inner;
+
+      let reported = self.lulu.lua.remove_app_data::<ReportedError>();
+      match (result, reported) {
+        (Err(err), _) => self.fail(ErrorKind::Render, &err.to_string()),
+        (Ok(_), Some(ReportedError(kind, err))) => self.fail(kind, &err),
+        (Ok(_), None) => {
+          self.last_frame = ctx.graphics(|g| {
+            g.get(egui::LayerId::background())
+              .map(|layer| layer.all_entries().cloned().collect())
+              .unwrap_or_default()
+          });
+        }
+      }
+    } else if self.error.is_some() {
+      ctx.graphics_mut(|g| {
+        let layer = g.entry(egui::LayerId::background());
+        for shape in &self.last_frame {
+          layer.add(shape.clip_rect, shape.shape.clone());
         }
       });
     }
 
-    let scheduler: mlua::Function = self
+    if self.error.is_some() && self.error_overlay(ctx) {
+      self.retry();
+      ctx.request_repaint();
+    }
+
+    self.poll_tasks(ctx);
+
+    if self.exit_code().is_some() {
+      ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+    }
+  }
+
+  /// Whether the root of the Lua tree is made of panels, as of the last
+  /// frame.
+  fn declares_panels(&self) -> bool {
+    self
       .lulu
       .lua
       .globals()
-      .get::<mlua::Table>("coroutine")
-      .unwrap()
-      .get("resume")
-      .unwrap();
-    let sched_co: mlua::Value = self
+      .get::<mlua::Table>("ui")
+      .and_then(|ui| ui.get::<mlua::Function>("declares_panels"))
+      .and_then(|declares| declares.call::<bool>(()))
+      .unwrap_or(false)
+  }
+
+  /// Runs the `ui.set_timeout`/`ui.set_interval` callbacks that are due and
+  /// schedules a repaint for the next deadline.
+  fn run_timers(&mut self, ctx: &egui::Context) {
+    let now = ctx.input(|i| i.time);
+    let result = self
       .lulu
       .lua
       .globals()
-      .get::<mlua::Table>("Future")
-      .unwrap()
-      .get("scheduler")
-      .unwrap();
+      .get::<mlua::Table>("ui")
+      .and_then(|ui| ui.get::<mlua::Function>("run_timers"))
+      .and_then(|run| run.call::<Option<f64>>(now));
+
+    match result {
+      Ok(Some(next)) => ctx.request_repaint_after(std::time::Duration::from_secs_f64(next)),
+      Ok(None) => {}
+      Err(err) => self.fail(ErrorKind::Timer, &err.to_string()),
+    }
+  }
 
-    scheduler.call::<mlua::Value>(sched_co.clone()).unwrap();
+  /// Polls the Lua async tasks once. Tasks waiting on a Rust future such as
+  /// `sleep` wake egui when it completes, so only tasks that yielded on
+  /// their own keep the app repainting.
+  fn poll_tasks(&mut self, ctx: &egui::Context) {
+    let Ok(poll) = self
+      .lulu
+      .lua
+      .globals()
+      .get::<mlua::Table>("ui")
+      .and_then(|ui| ui.get::<mlua::Function>("poll_tasks"))
+    else {
+      return;
+    };
+
+    let waker = std::task::Waker::from(std::sync::Arc::new(RepaintWaker(ctx.clone())));
+    let mut cx = std::task::Context::from_waker(&waker);
+    let call = poll.call_async::<bool>(());
+    let mut call = std::pin::pin!(call);
+
+    match std::future::Future::poll(call.as_mut(), &mut cx) {
+      std::task::Poll::Ready(Ok(true)) => ctx.request_repaint(),
+      std::task::Poll::Ready(Ok(false)) | std::task::Poll::Pending => {}
+      std::task::Poll::Ready(Err(err)) => self.fail(ErrorKind::Task, &err.to_string()),
+    }
+  }
+
+  /// Draws the current error over the dimmed last good frame. Returns whether
+  /// "Retry" was clicked.
+  fn error_overlay(&self, ctx: &egui::Context) -> bool {
+    let Some(err) = &self.error else { return false };
+
+    let screen = ctx.screen_rect();
+    ctx
+      .layer_painter(egui::LayerId::new(
+        egui::Order::PanelResizeLine,
+        egui::Id::new("lulu_error_backdrop"),
+      ))
+      .rect_filled(screen, 0.0, egui::Color32::from_black_alpha(160));
+
+    let can_retry = err.kind != ErrorKind::Init || self.main.is_some();
+    let mut retry = false;
+
+    egui::Window::new(err.kind.title())
+      .id(egui::Id::new("lulu_error_overlay"))
+      .collapsible(false)
+      .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+      .max_width(screen.width() - 32.0)
+      .max_height(screen.height() - 32.0)
+      .show(ctx, |ui| {
+        ui.label(
+          egui::RichText::new(&err.message)
+            .monospace()
+            .color(ui.visuals().error_fg_color),
+        );
+
+        if !err.traceback.is_empty() {
+          egui::CollapsingHeader::new("Traceback")
+            .default_open(true)
+            .show(ui, |ui| {
+              egui::ScrollArea::vertical()
+                .max_height(screen.height() / 2.0)
+                .show(ui, |ui| {
+                  for frame in &err.traceback {
+                    ui.label(egui::RichText::new(frame).monospace().small());
+                  }
+                });
+            });
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+          if can_retry && ui.button("Retry").clicked() {
+            retry = true;
+          }
+          if ui.button("Copy").clicked() {
+            ui.output_mut(|o| o.copied_text = err.to_string());
+          }
+        });
+      });
+
+    retry
+  }
+
+  /// The current error, if the app is showing one.
+  pub fn error(&self) -> Option<&AppError> {
+    self.error.as_ref()
+  }
+
+  /// The code passed to `ui.exit`, once Lua has asked the app to quit.
+  pub fn exit_code(&self) -> Option<i32> {
+    self.lulu.lua.app_data_ref::<ExitCode>().map(|code| code.0)
   }
 }
 
-async fn load_main(lulu: &mut Lulu) -> Result<mlua::Value, String> {
-  lulu.preload_mods().map_err(|e| e.to_string())?;
+impl eframe::App for LuluUiApp {
+  fn save(&mut self, storage: &mut dyn eframe::Storage) {
+    storage::save(&self.lulu.lua, storage);
+  }
+
+  fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+    // Changed frame back to _frame
+    match self
+      .lulu
+      .lua
+      .globals()
+      .set("cpu_usage", _frame.info().cpu_usage.unwrap_or(0.0))
+    {
+      Err(err) => self.fail(ErrorKind::Init, &err.to_string()),
+      Ok(_) => {}
+    }
+
+    self.frame(ctx);
+  }
+}
 
-  let main_name = lulu.find_mod("main").map_err(|e| e.to_string())?;
+/// Runs `ui.lua` and the main module, returning the init function.
+/// The name of the module the app was started from, set when it is not the
+/// conf's `main`, such as the file given to `--test=path`.
+pub struct MainModule(pub String);
+
+/// The name of the module the app runs, its `MainModule` or else `main`.
+pub fn main_module(lulu: &mut Lulu) -> mlua::Result<String> {
+  if let Some(name) = lulu.lua.app_data_ref::<MainModule>() {
+    return Ok(name.0.clone());
+  }
+  lulu.find_mod("main")
+}
+
+fn exec_app(lulu: &mut Lulu) -> Result<mlua::Value, String> {
+  let main_name = main_module(lulu).map_err(|e| e.to_string())?;
 
   // let ui_code = std::fs::read_to_string("src/lua/ui.lua").map_err(|e| e.to_string())?;
   let ui_code = include_str!("lua/ui.lua");
@@ -1928,21 +3498,49 @@ async fn load_main(lulu: &mut Lulu) -> Result<mlua::Value, String> {
   Ok(f)
 }
 
-pub async fn run(lulu: &mut Lulu) -> Result<(), eframe::Error> {
-  let options = eframe::NativeOptions {
-    viewport: egui::ViewportBuilder::default().with_inner_size([320.0, 240.0]),
-    ..Default::default()
-  };
+fn load_main(lulu: &mut Lulu) -> Result<mlua::Value, String> {
+  lulu.preload_mods().map_err(|e| e.to_string())?;
+  exec_app(lulu)
+}
 
-  let mut err: Option<String> = None;
+/// Loads `ui.lua` and the main module, returning the init function or the
+/// error that should be shown in place of the app.
+pub fn load(lulu: &mut Lulu) -> (Option<mlua::Value>, Option<String>) {
+  match load_main(lulu) {
+    Ok(f) => (Some(f), None),
+    Err(e) => (None, Some(e)),
+  }
+}
 
-  let main = match load_main(lulu).await {
-    Ok(f) => Some(f),
-    Err(e) => {
-      err = Some(e);
-      None
-    }
-  };
+pub fn install_fonts(ctx: &egui::Context) {
+  let mut fonts = egui::FontDefinitions::default();
+  fonts.font_data.insert(
+    "DejaVuSansMono".to_owned(),
+    egui::FontData::from_static(include_bytes!("../assets/fonts/DejaVuSansMono.ttf")),
+  );
+  fonts
+    .families
+    .entry(egui::FontFamily::Proportional)
+    .or_default()
+    .insert(0, "DejaVuSansMono".to_owned());
+  fonts
+    .families
+    .entry(egui::FontFamily::Monospace)
+    .or_default()
+    .insert(0, "DejaVuSansMono".to_owned());
+  ctx.set_fonts(fonts);
+}
+
+/// The manifest name of the main module, which eframe uses to pick the
+/// directory the app's storage is saved in.
+fn app_name(lulu: &mut Lulu) -> Option<String> {
+  let main_name = main_module(lulu).ok()?;
+  let lmod = lulu.mods.iter().find(|m| m.name == main_name)?;
+  lmod.conf.as_ref()?.manifest.as_ref()?.get::<String>("name").ok()
+}
+
+pub async fn run(lulu: &mut Lulu, entry: Option<&Path>) -> Result<(), eframe::Error> {
+  let window_conf = window::conf(lulu, entry);
 
   let title = if let Ok(modname) = lulu.find_mod("ui-title") {
     match lulu.mods.iter().find(|m| m.name == modname) {
@@ -1959,30 +3557,44 @@ pub async fn run(lulu: &mut Lulu) -> Result<(), eframe::Error> {
     "Lulu UI".to_string()
   };
 
-  let lulu = lulu.clone();
+  let mut viewport = egui::ViewportBuilder::default()
+    .with_title(title.clone())
+    .with_inner_size([320.0, 240.0]);
+  let mut conf_err = None;
+
+  if let Some(conf) = &window_conf {
+    match window::builder_from_lua(conf, viewport.clone(), &window::root(&lulu.lua)) {
+      Ok(builder) => viewport = builder,
+      Err(e) => conf_err = Some(e.to_string()),
+    }
+  }
+
+  let options = eframe::NativeOptions {
+    viewport,
+    ..Default::default()
+  };
+
+  let app_name = app_name(lulu).unwrap_or(title);
+  let mut lulu = lulu.clone();
+  let entry = entry.map(Path::to_path_buf);
 
   eframe::run_native(
-    &title,
+    &app_name,
     options,
-    Box::new(|cc| {
-      let mut fonts = egui::FontDefinitions::default();
-      fonts.font_data.insert(
-        "DejaVuSansMono".to_owned(),
-        egui::FontData::from_static(include_bytes!("../assets/fonts/DejaVuSansMono.ttf")),
-      );
-      fonts
-        .families
-        .entry(egui::FontFamily::Proportional)
-        .or_default()
-        .insert(0, "DejaVuSansMono".to_owned());
-      fonts
-        .families
-        .entry(egui::FontFamily::Monospace)
-        .or_default()
-        .insert(0, "DejaVuSansMono".to_owned());
-      cc.egui_ctx.set_fonts(fonts);
-
-      Box::new(LuluUiApp::new(cc, lulu, main, err))
+    Box::new(move |cc| {
+      install_fonts(&cc.egui_ctx);
+
+      let storage_err = storage::install(&lulu.lua, cc.storage).err().map(|e| e.to_string());
+      let (main, err) = load(&mut lulu);
+      let sources = Sources::collect(&lulu, entry.as_deref());
+
+      Box::new(LuluUiApp::new(
+        &cc.egui_ctx,
+        lulu,
+        main,
+        err.or(storage_err).or(conf_err),
+        sources,
+      ))
     }),
   )
 }
@@ -0,0 +1,185 @@
+use eframe::egui::{self, ViewportBuilder, ViewportCommand};
+use lulu::lulu::Lulu;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The directory of the app's `lulu.conf.lua`, which relative icon paths are
+/// resolved against, kept as Lua app data.
+struct ConfRoot(PathBuf);
+
+/// The directory relative icon paths are resolved against.
+pub fn root(lua: &mlua::Lua) -> PathBuf {
+  lua
+    .app_data_ref::<ConfRoot>()
+    .map(|root| root.0.clone())
+    .unwrap_or_default()
+}
+
+/// The `window` table of the main module's `lulu.conf.lua`. lulu only keeps
+/// a conf's `manifest`, `mods` and `macros`, and a bundle carries nothing
+/// else, so the table is read from `manifest.window` like the app name is
+/// read from `manifest.name`. Also remembers the conf's directory for `root`.
+pub fn conf(lulu: &mut Lulu, entry: Option<&Path>) -> Option<mlua::Table> {
+  let root = entry
+    .and_then(|entry| lulu::conf::find_lulu_conf(entry.to_path_buf()))
+    .and_then(|conf| conf.parent().map(Path::to_path_buf))
+    .unwrap_or_default();
+  lulu.lua.set_app_data(ConfRoot(root));
+
+  let main_name = crate::ui::main_module(lulu).ok();
+  lulu
+    .mods
+    .iter()
+    .find(|m| Some(&m.name) == main_name.as_ref())
+    .and_then(|m| m.conf.as_ref()?.manifest.clone())
+    .and_then(|m| m.get::<Option<mlua::Table>>("window").ok().flatten())
+}
+
+fn message(err: mlua::Error) -> String {
+  match err {
+    mlua::Error::RuntimeError(msg) => msg,
+    err => err.to_string(),
+  }
+}
+
+fn lua_vec2(value: mlua::Value) -> mlua::Result<egui::Vec2> {
+  match value {
+    mlua::Value::Table(table) => Ok(egui::vec2(table.get(1)?, table.get(2)?)),
+    _ => Err(mlua::Error::runtime("expected a { x, y } table")),
+  }
+}
+
+fn field_vec2(table: &mlua::Table, name: &str) -> mlua::Result<Option<egui::Vec2>> {
+  match table.get::<mlua::Value>(name)? {
+    mlua::Value::Nil => Ok(None),
+    value => lua_vec2(value)
+      .map(Some)
+      .map_err(|err| mlua::Error::runtime(format!("window.{}: {}", name, message(err)))),
+  }
+}
+
+fn load_icon(path: &Path) -> mlua::Result<egui::IconData> {
+  let bytes = std::fs::read(path)
+    .map_err(|err| mlua::Error::runtime(format!("window.icon: {}: {}", path.display(), err)))?;
+  eframe::icon_data::from_png_bytes(&bytes)
+    .map_err(|err| mlua::Error::runtime(format!("window.icon: {}: {}", path.display(), err)))
+}
+
+/// Applies a `window` table onto `builder`. Relative icon paths are resolved
+/// against `root`.
+///
+/// ```lua
+/// manifest = {
+///   name = "my-app",
+///   window = {
+///     title = "My App",
+///     size = { 800, 600 },
+///     min_size = { 320, 240 },
+///     resizable = true,
+///     icon = "assets/icon.png",
+///   },
+/// }
+/// ```
+pub fn builder_from_lua(
+  table: &mlua::Table,
+  mut builder: ViewportBuilder,
+  root: &Path,
+) -> mlua::Result<ViewportBuilder> {
+  if let Some(title) = table.get::<Option<String>>("title")? {
+    builder = builder.with_title(title);
+  }
+  if let Some(size) = field_vec2(table, "size")? {
+    builder = builder.with_inner_size(size);
+  }
+  if let Some(size) = field_vec2(table, "min_size")? {
+    builder = builder.with_min_inner_size(size);
+  }
+  if let Some(size) = field_vec2(table, "max_size")? {
+    builder = builder.with_max_inner_size(size);
+  }
+  if let Some(pos) = field_vec2(table, "position")? {
+    builder = builder.with_position(pos.to_pos2());
+  }
+  if let Some(resizable) = table.get::<Option<bool>>("resizable")? {
+    builder = builder.with_resizable(resizable);
+  }
+  if let Some(decorations) = table.get::<Option<bool>>("decorations")? {
+    builder = builder.with_decorations(decorations);
+  }
+  if let Some(transparent) = table.get::<Option<bool>>("transparent")? {
+    builder = builder.with_transparent(transparent);
+  }
+  if table.get::<Option<bool>>("always_on_top")?.unwrap_or(false) {
+    builder = builder.with_always_on_top();
+  }
+  if let Some(fullscreen) = table.get::<Option<bool>>("fullscreen")? {
+    builder = builder.with_fullscreen(fullscreen);
+  }
+  if let Some(maximized) = table.get::<Option<bool>>("maximized")? {
+    builder = builder.with_maximized(maximized);
+  }
+  if let Some(app_id) = table.get::<Option<String>>("app_id")? {
+    builder = builder.with_app_id(app_id);
+  }
+  if let Some(icon) = table.get::<Option<String>>("icon")? {
+    builder = builder.with_icon(Arc::new(load_icon(&root.join(icon))?));
+  }
+  Ok(builder)
+}
+
+fn command_from_lua(name: &str, value: mlua::Value, root: &Path) -> mlua::Result<ViewportCommand> {
+  let flag = |value: mlua::Value| value.as_boolean().unwrap_or(true);
+
+  Ok(match name {
+    "title" => ViewportCommand::Title(
+      value
+        .as_string()
+        .and_then(|s| s.to_str().ok().map(|s| s.to_string()))
+        .ok_or_else(|| mlua::Error::runtime("expected a title string"))?,
+    ),
+    "size" => ViewportCommand::InnerSize(lua_vec2(value)?),
+    "min_size" => ViewportCommand::MinInnerSize(lua_vec2(value)?),
+    "max_size" => ViewportCommand::MaxInnerSize(lua_vec2(value)?),
+    "position" => ViewportCommand::OuterPosition(lua_vec2(value)?.to_pos2()),
+    "resizable" => ViewportCommand::Resizable(flag(value)),
+    "decorations" => ViewportCommand::Decorations(flag(value)),
+    "transparent" => ViewportCommand::Transparent(flag(value)),
+    "visible" => ViewportCommand::Visible(flag(value)),
+    "always_on_top" => ViewportCommand::WindowLevel(if flag(value) {
+      egui::WindowLevel::AlwaysOnTop
+    } else {
+      egui::WindowLevel::Normal
+    }),
+    "fullscreen" => ViewportCommand::Fullscreen(flag(value)),
+    "maximized" => ViewportCommand::Maximized(flag(value)),
+    "minimized" => ViewportCommand::Minimized(flag(value)),
+    "icon" => ViewportCommand::Icon(match value {
+      mlua::Value::Nil => None,
+      value => Some(Arc::new(load_icon(&root.join(value.to_string()?))?)),
+    }),
+    "focus" => ViewportCommand::Focus,
+    "close" => ViewportCommand::Close,
+    _ => {
+      return Err(mlua::Error::runtime(format!(
+        "unknown window command '{}'",
+        name
+      )));
+    }
+  })
+}
+
+/// Registers `send_viewport_command(name, value)`, which `ui.window` uses to
+/// change the window the current frame is rendering into.
+pub fn register(lua: &mlua::Lua) -> mlua::Result<()> {
+  lua.globals().set(
+    "send_viewport_command",
+    lua.create_function(|lua, (name, value): (String, mlua::Value)| {
+      let command = command_from_lua(&name, value, &root(lua))
+        .map_err(|err| mlua::Error::runtime(format!("ui.window.set_{}: {}", name, message(err))))?;
+      if let Some(ctx) = lua.app_data_ref::<egui::Context>() {
+        ctx.send_viewport_cmd(command);
+      }
+      Ok(())
+    })?,
+  )
+}
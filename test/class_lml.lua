@@ -14,10 +14,9 @@ local class! @Component() @StatedComponent({
   selectables = {volvo = "Volvo", marcedes = "Marcedes", ferrari = "Ferrari"}
 }) @AutoRender Body:Widget, {
   prepare(){
-    async(function()
-      sleep(1)
+    ui.set_timeout(function()
       self.loading:set(false)
-    end)
+    end, 1)
   }
   rebuild(){
     -- override rebuild
@@ -0,0 +1,38 @@
+() @namespace(ui) =>
+
+  local colors = { "red", "green", "blue" }
+
+  local (self) @AutoRender @StatedComponent({
+    color = "none"
+  }) @Component() AppRoot =>
+    return VBox {
+      children = {
+        Label {
+          text = f"Color: {self.color:get()}",
+          tooltip = "Right click to pick a color",
+          context_menu = function(menu)
+            for _, color in ipairs(colors) do
+              if menu:button(color).clicked then
+                self.color:set(color)
+                menu:close_menu()
+              end
+            end
+          end
+        },
+        Button {
+          text = "Clear",
+          tooltip = VBox {
+            children = {
+              Heading { text = "Clear" },
+              Label { text = "Sets the color back to none." }
+            }
+          },
+          on_clicked = function()
+            self.color:set("none")
+          end
+        }
+      }
+    }
+  end
+
+end
@@ -0,0 +1,523 @@
+
+() @namespace(ui) =>
+
+  local (self, props) @Component() Counter =>
+    return VBox {
+      children = {
+        Label {
+          text = f"Clicked: {props.clicked:get()}"
+        },
+        HBox {
+          Button {
+            text = "Add",
+            on_clicked = function()
+              props.clicked:add(1)
+            end
+          },
+          Input {
+            value = props.name,
+            id = "name"
+          }
+        }
+      }
+    }
+  end
+
+  local (self) @StatedComponent({ count = 0 }) @Component() Tally =>
+    return Button {
+      text = f"{self.props.key}: {self.count:get()}",
+      on_clicked = function()
+        self.count:add(1)
+      end
+    }
+  end
+
+  local (self) @StatedComponent({ order = { "a", "b" } }) @Component() Tallies =>
+    local tallies = {}
+    for i, key in ipairs(self.order:get()) do
+      tallies[i] = Tally { key = key }
+    end
+    return HBox { children = tallies }
+  end
+
+  local (self) @Persisted("dock_test", { "layout" }) @StatedComponent({
+    layout = { tabs = { "a", "b" }, selected = "a" }
+  }) @Component() Workspace =>
+    return Dock {
+      layout = self.layout,
+      children = {
+        Label { key = "a", title = "A", text = "content a" },
+        Label { key = "b", title = "B", text = "content b" },
+      }
+    }
+  end
+
+  local (self) @Persisted("counter_test", { "count" }) @StatedComponent({ count = 0 }) @Component() SavedCount =>
+    return Button {
+      text = f"Saved: {self.count:get()}",
+      on_clicked = function()
+        self.count:add(1)
+      end
+    }
+  end
+
+  local (self, props) @Component() Row =>
+    return Label { text = props.text }
+  end
+
+  local (self, props) @Component() Loaded =>
+    return Label { text = "loaded " .. tostring(props.data:get().value) }
+  end
+
+  test.case("clicking add increments the counter", function(t)
+    local clicked = State(0)
+    t:mount(Counter, { clicked = clicked })
+    t:frame()
+
+    t:click("Add")
+    t:assert_eq(clicked:get(), 1)
+
+    t:click({ name = "button", text = "Add" })
+    t:assert_eq(clicked:get(), 2)
+  end)
+
+  test.case("typing updates the bound state", function(t)
+    local name = State("")
+    t:mount(Counter, { clicked = State(0), name = name })
+    t:frame()
+
+    t:click("input")
+    t:type_text("lulu")
+    t:assert_eq(name:get(), "lulu")
+    t:assert(t:get("input").response.has_focus, "input should keep focus")
+  end)
+
+  test.case("keyed children keep their state when the parent rebuilds", function(t)
+    local root = t:mount(Tallies, {})
+    t:frame()
+
+    t:click("b: 0")
+    t:frame()
+
+    root.order:set({ "b", "a" })
+    t:frame()
+    t:get("b: 1")
+    t:get("a: 0")
+  end)
+
+  test.case("an error boundary shows its fallback until reset", function(t)
+    local boundary = t:mount(ErrorBoundary, {
+      fallback = function(err, reset)
+        return Button { text = "Reset", on_clicked = reset }
+      end,
+      children = {
+        Button { text = "Fail", on_clicked = function() error("failed") end }
+      }
+    })
+    t:frame()
+
+    t:click("Fail")
+    t:frame()
+    t:assert(boundary.error ~= nil, "the error should be caught")
+
+    t:click("Reset")
+    t:frame()
+    t:assert_eq(boundary.error, nil)
+    t:get("Fail")
+  end)
+
+  test.case("closing the selected tab selects its neighbour", function(t)
+    local selected = State("b")
+    local tabs = t:mount(Tabs, {
+      selected = selected,
+      closable = true,
+      children = {
+        Label { key = "a", title = "A", text = "content a" },
+        Label { key = "b", title = "B", text = "content b" },
+      }
+    })
+    t:frame()
+    t:get("content b")
+
+    tabs:close("b")
+    t:frame()
+    t:assert_eq(selected:get(), "a")
+    t:get("content a")
+  end)
+
+
+  test.case("the screen takes its size from manifest.window in lulu.conf.lua", function(t)
+    local separator = t:mount(Separator {})
+    t:frame()
+    -- test/lulu.conf.lua sets a 360x280 window, and the root ui has an 8px margin.
+    t:assert_eq(separator.response.rect[3], 352)
+  end)
+
+  test.case("a virtual list only builds the rows in view and keeps its place on insert", function(t)
+    local items = Vec()
+    for i = 1, 1000 do
+      items:push({ id = i, text = f"item {i}" })
+    end
+    local rows = State(items)
+    local built = 0
+    local list = t:mount(VirtualList {
+      items = rows,
+      key = "id",
+      render = function(item)
+        built = built + 1
+        return Label { text = item.text }
+      end
+    })
+    t:frame(2)
+    t:assert(built > 0 and built < 30, "only the rows in view should be built, built " .. built)
+    t:assert_eq(list._rows[100], nil)
+
+    list:scroll_to(500)
+    t:frame(3)
+    -- Rows are kept by the list rather than as its children.
+    local function top_of(id)
+      local row = list._rows[id]
+      return row and row.nodes:get(1).response.rect[2]
+    end
+    local top = top_of(500)
+    t:assert(top ~= nil, "row 500 should be in view")
+
+    local inserted = Vec({ { id = 0, text = "item 0" } })
+    rows:get():for_each(function(item) inserted:push(item) end)
+    rows:set(inserted)
+    t:frame(3)
+    t:assert_eq(top_of(500), top)
+  end)
+
+  test.case("a batch that raises still runs its effects", function(t)
+    local count = State(0)
+    local seen
+    local watcher = effect(function() seen = count:get() end)
+    local ok = pcall(ui.batch, function()
+      count:set(1)
+      error("failed")
+    end)
+    t:assert(not ok, "the error should be raised again")
+    count:set(2)
+    t:assert_eq(seen, 2)
+    watcher:unsubscribe()
+  end)
+
+  test.case("only the topmost modal gets keys and closes on Escape", function(t)
+    local name = State("")
+    local outer, inner = State(false), State(false)
+    t:mount(VBox {
+      children = {
+        Input { value = name, id = "behind" },
+        Modal {
+          open = outer,
+          title = "Outer",
+          children = {
+            Modal { open = inner, title = "Inner", children = { Label { text = "inner" } } }
+          }
+        }
+      }
+    })
+    t:frame()
+    t:click("input")
+    outer:set(true)
+    inner:set(true)
+    t:frame(2)
+
+    t:type_text("x")
+    t:assert_eq(name:get(), "", "typing should not reach the input behind the modals")
+
+    t:key("Escape")
+    t:assert_eq(inner:get(), false)
+    t:assert_eq(outer:get(), true)
+
+    t:key("Escape")
+    t:assert_eq(outer:get(), false)
+    t:click("input")
+    t:type_text("ok")
+    t:assert_eq(name:get(), "ok")
+  end)
+
+
+  test.case("dropping a tab on the edge of a dock splits it and the layout is persisted", function(t)
+    ui.storage.remove("dock_test.layout")
+    local first = t:mount(Workspace, {})
+    t:frame()
+
+    -- Drag tab "A" from the tab row onto the right edge of the dock.
+    push_input_event({ type = "pointer_moved", x = 12, y = 17 }); t:frame()
+    push_input_event({ type = "pointer_button", x = 12, y = 17, pressed = true }); t:frame()
+    for i = 1, 5 do
+      push_input_event({ type = "pointer_moved", x = 12 + 58 * i, y = 17 + 27 * i }); t:frame()
+    end
+    push_input_event({ type = "pointer_button", x = 300, y = 152, pressed = false }); t:frame(2)
+
+    local layout = first.layout:get()
+    t:assert_eq(layout.split, "horizontal")
+    t:assert_eq(layout.children[1].tabs[1], "b")
+    t:assert_eq(layout.children[2].tabs[1], "a")
+
+    local second = t:mount(Workspace, {})
+    t:frame()
+    t:assert_eq(second.layout:get().split, "horizontal")
+    t:assert_eq(second.layout:get().children[2].tabs[1], "a")
+    t:assert(t:get("content a").response ~= nil, "both groups should be shown")
+    t:assert(t:get("content b").response ~= nil, "both groups should be shown")
+  end)
+
+
+  test.case("storage values and persisted state round-trip", function(t)
+    ui.storage.set("counter_test.value", { name = "lulu", sizes = { 1, 2 } })
+    local value = ui.storage.get("counter_test.value")
+    t:assert_eq(value.name, "lulu")
+    t:assert_eq(value.sizes[2], 2)
+    ui.storage.remove("counter_test.value")
+    t:assert_eq(ui.storage.get("counter_test.value"), nil)
+
+    ui.storage.remove("counter_test.count")
+    t:mount(SavedCount, {})
+    t:frame()
+    t:click("Saved: 0")
+    t:frame()
+
+    -- A fresh instance starts from the stored count.
+    t:mount(SavedCount, {})
+    t:frame()
+    t:get("Saved: 1")
+  end)
+
+
+  test.case("a state change or ui.request_repaint asks for a repaint", function(t)
+    local calls = {}
+    local original = _G.request_repaint
+    _G.request_repaint = function(after)
+      table.insert(calls, after or 0)
+      original(after)
+    end
+    local value = State(0)
+    value:set(1)
+    ui.request_repaint(0.5)
+    _G.request_repaint = original
+
+    t:assert_eq(#calls, 2)
+    t:assert_eq(calls[2], 0.5)
+  end)
+
+
+  test.case("an interval fires until it is cleared and a timeout fires once", function(t)
+    local ticks, fired = 0, 0
+    local id = ui.set_interval(function() ticks = ticks + 1 end, 1 / 50)
+    ui.set_timeout(function() fired = fired + 1 end, 1 / 20)
+    -- Headless frames are at least 1/60s apart.
+    t:frame(12)
+    t:assert(ticks >= 2, "the interval should fire repeatedly, fired " .. ticks)
+    t:assert_eq(fired, 1)
+
+    ui.clear_timer(id)
+    local cleared = ticks
+    t:frame(6)
+    t:assert_eq(ticks, cleared)
+    t:assert_eq(fired, 1)
+  end)
+
+
+  test.case("clicking a table header sorts and clicking a row selects it", function(t)
+    local selected = State(0)
+    local sorted
+    t:mount(Table {
+      rows = { { name = "b" }, { name = "a" } },
+      selected = selected,
+      columns = { { header = "Name", key = "name", sortable = true } },
+      on_sort = function(_, e) sorted = e end,
+    })
+    t:frame(2)
+
+    -- Tables draw their cells directly, so they are clicked by position: a
+    -- 20px header, then 18px rows.
+    local function click_at(x, y)
+      push_input_event({ type = "pointer_moved", x = x, y = y }); t:frame()
+      push_input_event({ type = "pointer_button", x = x, y = y, pressed = true }); t:frame()
+      push_input_event({ type = "pointer_button", x = x, y = y, pressed = false }); t:frame()
+    end
+
+    click_at(20, 18)
+    t:assert_eq(sorted.value, "name")
+    t:assert_eq(sorted.ascending, true)
+    click_at(20, 18)
+    t:assert_eq(sorted.ascending, false)
+
+    click_at(20, 61)
+    t:assert_eq(selected:get(), 2)
+  end)
+
+
+  test.case("a computed state follows every state it reads", function(t)
+    local a, b = State(1), State(2)
+    local sum = computed(function() return a:get() + b:get() end)
+    t:assert_eq(sum:get(), 3)
+
+    a:set(10)
+    t:assert_eq(sum:get(), 12)
+    b:set(5)
+    t:assert_eq(sum:get(), 15)
+  end)
+
+
+  test.case("closing a side panel gives its room to the central panel", function(t)
+    local open = State(true)
+    t:mount(Style {
+      children = {
+        SidePanel { id = "side", open = open, children = { Label { text = "side" } } },
+        CentralPanel { children = { Label { text = "main" } } },
+      }
+    })
+    t:frame(2)
+    t:assert(ui.declares_panels(), "the app should lay out its own panels")
+    local side = t:get("side").response.rect
+    t:assert(t:get("main").response.rect[1] > side[3], "the central panel should start right of the side panel")
+
+    open:set(false)
+    -- Long enough for the panel to finish sliding out.
+    t:frame(30)
+    t:assert_eq(t:get("main").response.rect[1], side[1])
+  end)
+
+
+  test.case("menu shortcuts run enabled items and toggle checked ones", function(t)
+    local quit, sidebar = 0, State(false)
+    t:mount(MenuBar {
+      children = {
+        Menu {
+          text = "File",
+          children = {
+            MenuItem { text = "Quit", shortcut = "Ctrl+Q", on_clicked = function() quit = quit + 1 end },
+            MenuItem { text = "Sidebar", shortcut = "Ctrl+B", checked = sidebar },
+            MenuItem { text = "Off", shortcut = "Ctrl+O", enabled = false, on_clicked = function() quit = 100 end },
+          }
+        }
+      }
+    })
+    t:frame()
+
+    t:key("Q", { ctrl = true })
+    t:assert_eq(quit, 1)
+    t:key("B", { ctrl = true })
+    t:assert_eq(sidebar:get(), true)
+    t:key("O", { ctrl = true })
+    t:assert_eq(quit, 1, "a disabled item's shortcut should do nothing")
+  end)
+
+
+  test.case("a virtual list unmounts the rows that leave the view", function(t)
+    local items = Vec()
+    for i = 1, 200 do
+      items:push({ id = i, text = f"item {i}" })
+    end
+    local unmounted = {}
+    local list = t:mount(VirtualList {
+      items = items,
+      key = "id",
+      render = function(item)
+        local row = Row { text = item.text }
+        row.unmounted = function() unmounted[item.id] = true end
+        return row
+      end
+    })
+    t:frame(2)
+    t:assert(list._rows[1] ~= nil, "row 1 should be in view")
+
+    list:scroll_to(150)
+    t:frame(3)
+    t:assert_eq(list._rows[1], nil)
+    t:assert(unmounted[1], "row 1 should be unmounted once out of view")
+    t:assert(not unmounted[150], "row 150 is in view")
+  end)
+
+  test.case("inserting into a virtual list only looks up a few keys", function(t)
+    local items = Vec()
+    for i = 1, 1000 do
+      items:push({ id = i, text = f"item {i}" })
+    end
+    local rows = State(items)
+    local lookups = 0
+    local list = t:mount(VirtualList {
+      items = rows,
+      key = function(item)
+        lookups = lookups + 1
+        return item.id
+      end,
+      render = function(item) return Label { text = item.text } end
+    })
+    list:scroll_to(500)
+    t:frame(3)
+    local function top_of(id)
+      local row = list._rows[id]
+      return row and row.nodes:get(1).response.rect[2]
+    end
+    local top = top_of(500)
+    t:assert(top ~= nil, "row 500 should be in view")
+
+    lookups = 0
+    local inserted = Vec({ { id = 0, text = "item 0" } })
+    rows:get():for_each(function(item) inserted:push(item) end)
+    rows:set(inserted)
+    t:frame()
+    t:assert(lookups < 100, "only the rows in view and a few more should be looked up, looked up " .. lookups)
+    t:frame(2)
+    t:assert_eq(top_of(500), top)
+  end)
+
+
+  test.case("suspense shows its fallback from the first frame", function(t)
+    local data = resource(function()
+      sleep(1 / 20)
+      return "data"
+    end)
+    t:mount(Suspense {
+      fallback = Label { text = "loading" },
+      child = Loaded { data = data }
+    })
+    t:frame()
+    local fallback = t:get("loading")
+    t:assert(fallback.response ~= nil, "the fallback should show on the first frame")
+    -- The children drawn before the resource was known to be loading take no space.
+    t:assert_eq(fallback.response.rect[2], 8)
+
+    for _ = 1, 60 do
+      if not data:get().loading then break end
+      t:frame()
+    end
+    t:frame(2)
+    t:assert(t:get("loaded data").response ~= nil, "the children should show once loaded")
+  end)
+
+
+  test.case("dragging a dock split in small steps moves it", function(t)
+    local layout = State({
+      split = "horizontal",
+      fraction = 0.5,
+      children = { { tabs = { "a" } }, { tabs = { "b" } } }
+    })
+    t:mount(Dock {
+      layout = layout,
+      children = {
+        Label { key = "a", title = "A", text = "content a" },
+        Label { key = "b", title = "B", text = "content b" },
+      }
+    })
+    t:frame()
+
+    -- The 8px handle sits halfway along the 344px wide root.
+    push_input_event({ type = "pointer_moved", x = 180, y = 100 }); t:frame()
+    push_input_event({ type = "pointer_button", x = 180, y = 100, pressed = true }); t:frame()
+    for i = 1, 20 do
+      push_input_event({ type = "pointer_moved", x = 180 + i, y = 100 }); t:frame()
+    end
+    push_input_event({ type = "pointer_button", x = 200, y = 100, pressed = false }); t:frame()
+
+    -- 20px of the 336px the two sides share.
+    local fraction = layout:get().fraction
+    t:assert(math.abs(fraction - (0.5 + 20 / 336)) < 1 / 336, "the split should follow the pointer, at " .. fraction)
+  end)
+
+end
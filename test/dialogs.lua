@@ -0,0 +1,58 @@
+() @namespace(ui) =>
+
+  local (self) @AutoRender @StatedComponent({
+    count = 3,
+    about = false
+  }) @Component() AppRoot =>
+    return VBox {
+      children = {
+        Label {
+          text = f"Items: {self.count:get()}"
+        },
+        HBox {
+          Button {
+            text = "Clear",
+            on_clicked = function()
+              async(function()
+                if ui.confirm { title = "Clear", message = "Remove every item?" } == "OK" then
+                  self.count:set(0)
+                end
+              end)
+            end
+          },
+          Popup {
+            anchor = Button { text = "Add" },
+            children = {
+              Button {
+                text = "One",
+                on_clicked = function()
+                  self.count:add(1)
+                end
+              },
+              Button {
+                text = "Ten",
+                on_clicked = function()
+                  self.count:add(10)
+                end
+              }
+            }
+          },
+          Button {
+            text = "About",
+            on_clicked = function()
+              self.about:set(true)
+            end
+          }
+        },
+        Modal {
+          open = self.about,
+          title = "About",
+          children = {
+            Label { text = "Escape or a click outside closes this." }
+          }
+        }
+      }
+    }
+  end
+
+end
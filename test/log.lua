@@ -0,0 +1,35 @@
+() @namespace(ui) =>
+
+  local lines = Vec()
+  for i = 1, 5000 do
+    lines:push({ id = i, text = f"line {i}" .. string.rep("\n  detail", i % 3) })
+  end
+
+  local (self) @AutoRender @StatedComponent({
+    lines = lines,
+    next_id = 5001
+  }) @Component() AppRoot =>
+    return VBox {
+      children = {
+        Button {
+          text = "Prepend a line",
+          on_clicked = function()
+            local id = self.next_id:get()
+            local prepended = Vec({ { id = id, text = f"line {id} (new)" } })
+            self.lines:get():for_each(function(line) prepended:push(line) end)
+            self.next_id:set(id + 1)
+            self.lines:set(prepended)
+          end
+        },
+        VirtualList {
+          items = self.lines,
+          key = "id",
+          render = function(line)
+            return Label { text = line.text }
+          end
+        }
+      }
+    }
+  end
+
+end
@@ -1,8 +1,12 @@
 manifest = {
-  name = "hello"
+  name = "hello",
+  window = {
+    title = "Lulu UI demo",
+    size = { 360, 280 },
+    min_size = { 240, 160 },
+  }
 }
 
 mods = {
   main = "main.lua"
 }
-
@@ -0,0 +1,97 @@
+() @namespace(ui) =>
+
+  local (self) @AutoRender @StatedComponent({
+    sidebar = true,
+    selected = "Inbox"
+  }) @Component() AppRoot =>
+    local folders = { "Inbox", "Sent", "Drafts", "Trash" }
+    return Style {
+      children = {
+        TopBottomPanel {
+          id = "toolbar",
+          children = {
+            MenuBar {
+              children = {
+                Menu {
+                  text = "File",
+                  children = {
+                    MenuItem {
+                      text = "Empty trash",
+                      shortcut = "Ctrl+Shift+Delete",
+                      on_clicked = function()
+                        print("Emptied trash")
+                      end
+                    },
+                    Separator {},
+                    MenuItem {
+                      text = "Quit",
+                      shortcut = "Ctrl+Q",
+                      on_clicked = function()
+                        ui.exit()
+                      end
+                    }
+                  }
+                },
+                Menu {
+                  text = "View",
+                  children = {
+                    MenuItem { text = "Sidebar", shortcut = "Ctrl+B", checked = self.sidebar },
+                    Menu {
+                      text = "Go to",
+                      children = {
+                        Each {
+                          items = folders,
+                          render = function(folder)
+                            return MenuItem {
+                              text = folder,
+                              enabled = folder ~= "Trash",
+                              on_clicked = function()
+                                self.selected:set(folder)
+                              end
+                            }
+                          end
+                        }
+                      }
+                    }
+                  }
+                }
+              }
+            }
+          }
+        },
+        TopBottomPanel {
+          id = "status",
+          side = "bottom",
+          children = {
+            Label { text = f"{#folders} folders" }
+          }
+        },
+        SidePanel {
+          id = "folders",
+          open = self.sidebar,
+          default_width = 160,
+          min_width = 100,
+          children = {
+            Each {
+              items = folders,
+              render = function(folder)
+                return Button {
+                  text = folder,
+                  on_clicked = function()
+                    self.selected:set(folder)
+                  end
+                }
+              end
+            }
+          }
+        },
+        CentralPanel {
+          children = {
+            Heading { text = self.selected }
+          }
+        }
+      }
+    }
+  end
+
+end
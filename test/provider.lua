@@ -0,0 +1,61 @@
+() @namespace(ui) =>
+
+  local (self, props) @Component() AddButton =>
+    local clicked = use_context("clicked")
+    return Button {
+      text = f"Add",
+      on_clicked = function()
+        clicked:add(1)
+      end
+    }
+  end
+
+  local (self, props) @Component() SubButton =>
+    local clicked = use_context("clicked")
+    return Button {
+      text = f"Subtract",
+      on_clicked = function()
+        clicked:sub(1)
+      end
+    }
+  end
+
+  local (self, props) @Component() Buttons =>
+    return HBox {
+      AddButton {},
+      SubButton {},
+      props.children
+    }
+  end
+
+  local (self) @AutoRender @StatedComponent({
+    clicked = 0
+  }) @Component() AppRoot =>
+    return Provider {
+      values = { clicked = self.clicked },
+      child = VBox {
+        children = {
+          {
+            match! self.clicked:get(), {
+              (val > 10) {
+                return ColoredLabel {
+                  text = "Too much",
+                  color = { 255, 255, 255, 255 }
+                }
+              }
+            }
+          },
+          Label {
+            text = f"Clicked: {self.clicked:get()}"
+          },
+          Buttons {
+            children = {
+              Button { text = "Normal button" }
+            }
+          }
+        }
+      }
+    }
+  end
+
+end
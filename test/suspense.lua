@@ -0,0 +1,33 @@
+
+() @namespace(ui) =>
+
+  local (self, props) @Component() Items =>
+    return VList {
+      items = props.items:get().value,
+      render = function(e)
+        return Label {
+          text = e
+        }
+      end
+    }
+  end
+
+  local (self) @AutoRender @UIOverride('prepare', function(self)
+    self.items = resource(function()
+      sleep(1)
+      return Vec({ "a", "b", "c", "d" })
+    end)
+  end) @Component() AppRoot =>
+    return Frame {
+      children = {
+        Suspense {
+          fallback = Spinner {},
+          child = Items {
+            items = self.items
+          }
+        }
+      }
+    }
+  end
+
+end
\ No newline at end of file
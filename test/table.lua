@@ -0,0 +1,46 @@
+() @namespace(ui) =>
+
+  local people = Vec()
+  for i = 1, 10000 do
+    people:push({ name = f"Person {i}", age = 18 + (i * 7) % 60 })
+  end
+
+  local (self) @AutoRender @StatedComponent({
+    selected = 0,
+    people = people
+  }) @Component() AppRoot =>
+    return VBox {
+      children = {
+        Label {
+          text = f"Selected: {self.selected:get()}"
+        },
+        Table {
+          rows = self.people,
+          selected = self.selected,
+          columns = {
+            { header = "Name", key = "name", width = "remainder", sortable = true },
+            { header = "Age", key = "age", width = 60, align = "end", sortable = true },
+            {
+              header = "",
+              width = 80,
+              render = function(ui, person, row)
+                return Button { text = "Greet", on_clicked = function() print(f"Hello {person.name}") end }
+              end
+            },
+          },
+          on_sort = function(_, e)
+            local items = self.people:get().items
+            table.sort(items, function(a, b)
+              if e.ascending then
+                return a[e.value] < b[e.value]
+              end
+              return a[e.value] > b[e.value]
+            end)
+            self.people:set(Vec(items))
+          end
+        }
+      }
+    }
+  end
+
+end
@@ -0,0 +1,47 @@
+() @namespace(ui) =>
+
+  local (self, props) @Component() Document =>
+    return VBox {
+      children = {
+        Heading { text = props.title },
+        Label { text = props.text }
+      }
+    }
+  end
+
+  local (self) @AutoRender @Persisted("tabs", { "layout" }) @StatedComponent({
+    layout = {
+      split = "horizontal",
+      fraction = 0.3,
+      children = {
+        { tabs = { "files" }, selected = "files" },
+        { tabs = { "readme", "notes" }, selected = "readme" },
+      }
+    },
+    selected = "readme"
+  }) @Component() AppRoot =>
+    return Dock {
+      layout = self.layout,
+      closable = true,
+      children = {
+        Document { key = "files", title = "Files", closable = false, text = "readme.md\nnotes.txt" },
+        Document { key = "readme", title = "readme.md", text = "Drag a tab onto another group, or onto an edge to split it." },
+        Document { key = "notes", title = "notes.txt", text = "The layout is saved between runs." },
+        VBox {
+          key = "about",
+          title = "About",
+          children = {
+            Tabs {
+              selected = self.selected,
+              children = {
+                Label { key = "readme", title = "Readme", text = "A plain row of tabs." },
+                Label { key = "license", title = "License", text = "MIT" },
+              }
+            }
+          }
+        }
+      }
+    }
+  end
+
+end